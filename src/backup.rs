@@ -0,0 +1,258 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+};
+use tokio::fs;
+
+use async_recursion::async_recursion;
+use chrono::{Local, NaiveDateTime, TimeDelta};
+use eyre::{Result, bail, eyre};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::transaction::Transaction;
+
+/// Format of the timestamps in the names of backups.
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d-%H-%M-%S";
+
+static BACKUP_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<target>.+)\.nyoom-(?P<timestamp>\d{4}-\d{2}-\d{2}-\d{2}-\d{2}-\d{2})\.bak$")
+        .unwrap()
+});
+
+/// Returns the name of a new backup of `target` in the profile.
+pub fn backup_name(target: &str, now: &NaiveDateTime) -> String {
+    format!("{target}.nyoom-{}.bak", now.format(TIMESTAMP_FORMAT))
+}
+
+/// A backup of the `chrome` directory or a user file that nyoom made in a profile.
+#[derive(Clone, Debug)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// Name of the file or directory in the profile that was backed up
+    pub target: String,
+    pub created: NaiveDateTime,
+    /// Total size of the backed up files in bytes
+    pub size: u64,
+}
+
+impl Backup {
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn age(&self) -> TimeDelta {
+        Local::now().naive_local() - self.created
+    }
+}
+
+/// Formats the age of a backup, e.g. `3 days ago`.
+pub fn format_age(age: TimeDelta) -> String {
+    let (value, unit) = if age.num_days() > 0 {
+        (age.num_days(), "day")
+    } else if age.num_hours() > 0 {
+        (age.num_hours(), "hour")
+    } else if age.num_minutes() > 0 {
+        (age.num_minutes(), "minute")
+    } else {
+        return "just now".to_owned();
+    };
+
+    format!("{value} {unit}{} ago", if value == 1 { "" } else { "s" })
+}
+
+#[async_recursion]
+async fn size(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path).await?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut total = 0;
+
+    let mut entries = fs::read_dir(path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        total += size(&entry.path()).await?;
+    }
+
+    Ok(total)
+}
+
+/// Finds the backups in a profile, sorted from newest to oldest.
+pub async fn discover(profile: &Path) -> Result<Vec<Backup>> {
+    let mut backups = Vec::new();
+
+    let mut entries = fs::read_dir(profile).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(captures) = BACKUP_REGEX.captures(&name) else {
+            continue;
+        };
+
+        let Ok(created) = NaiveDateTime::parse_from_str(&captures["timestamp"], TIMESTAMP_FORMAT)
+        else {
+            continue;
+        };
+
+        backups.push(Backup {
+            path: entry.path(),
+            target: captures["target"].to_owned(),
+            created,
+            size: size(&entry.path()).await?,
+        });
+    }
+
+    backups.sort_by(|a, b| {
+        b.created
+            .cmp(&a.created)
+            .then_with(|| a.target.cmp(&b.target))
+    });
+
+    Ok(backups)
+}
+
+/// Finds a backup by its name, or the newest backup of a target like `chrome` or `user.js`.
+pub async fn find(profile: &Path, name: &str) -> Result<Backup> {
+    discover(profile)
+        .await?
+        .into_iter()
+        .find(|backup| backup.name() == name || backup.target == name)
+        .ok_or_else(|| eyre!("no backup named {name:?} found in {}", profile.display()))
+}
+
+/// Restores a backup in place of its target, which is backed up in turn if it exists.
+///
+/// Both steps are renames that are rolled back if either fails.
+pub async fn restore(profile: &Path, backup: &Backup) -> Result<()> {
+    let target = profile.join(&backup.target);
+    let mut tx = Transaction::default();
+
+    let result = async {
+        if target.exists() {
+            let now = Local::now().naive_local();
+            tx.rename(&target, &profile.join(backup_name(&backup.target, &now)))
+                .await?;
+        }
+
+        tx.rename(&backup.path, &target).await
+    }
+    .await;
+
+    if let Err(err) = result {
+        tx.rollback().await?;
+        return Err(err);
+    }
+
+    tx.commit().await
+}
+
+/// A maximum age of backups, written as a number followed by `h` (hours), `d` (days) or `w` (weeks).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxAge(TimeDelta);
+
+impl FromStr for MaxAge {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some(unit) = s.chars().last() else {
+            bail!("expected an age like `30d`");
+        };
+
+        let value: u32 = s[..s.len() - unit.len_utf8()]
+            .parse()
+            .map_err(|_| eyre!("invalid age {s:?}, expected e.g. `30d`"))?;
+        let value = i64::from(value);
+
+        Ok(Self(match unit {
+            'h' => TimeDelta::hours(value),
+            'd' => TimeDelta::days(value),
+            'w' => TimeDelta::weeks(value),
+            _ => bail!("invalid unit in age {s:?}, expected `h`, `d` or `w`"),
+        }))
+    }
+}
+
+impl fmt::Display for MaxAge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hours = self.0.num_hours();
+
+        if hours % (24 * 7) == 0 {
+            write!(f, "{}w", hours / (24 * 7))
+        } else if hours % 24 == 0 {
+            write!(f, "{}d", hours / 24)
+        } else {
+            write!(f, "{hours}h")
+        }
+    }
+}
+
+impl Serialize for MaxAge {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MaxAge {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Rules for pruning backups; a backup is pruned if it violates any of them.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct PruneRules {
+    /// Number of backups to keep of each file or directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+    /// Maximum age of backups to keep
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<MaxAge>,
+}
+
+impl PruneRules {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Finds the backups in a profile that should be pruned according to `rules`.
+pub async fn prunable(profile: &Path, rules: &PruneRules) -> Result<Vec<Backup>> {
+    let mut counts = BTreeMap::<String, usize>::new();
+
+    Ok(discover(profile)
+        .await?
+        .into_iter()
+        .filter(|backup| {
+            let count = counts.entry(backup.target.clone()).or_default();
+            *count += 1;
+
+            rules.keep.is_some_and(|keep| *count > keep)
+                || rules.max_age.is_some_and(|MaxAge(max)| backup.age() > max)
+        })
+        .collect())
+}
+
+/// Deletes a backup.
+pub async fn remove(backup: &Backup) -> Result<()> {
+    if backup.path.is_dir() {
+        fs::remove_dir_all(&backup.path).await?;
+    } else {
+        fs::remove_file(&backup.path).await?;
+    }
+
+    Ok(())
+}
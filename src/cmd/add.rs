@@ -2,10 +2,13 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::path::{Component, Path};
+
 use clap::Parser;
 use eyre::{Result, bail};
+use reqwest::header::{HeaderName, HeaderValue};
 
-use crate::{config, source::ParsedSource};
+use crate::{config, source::ParsedSource, utils::integrity::Integrity};
 
 #[derive(Parser)]
 pub struct AddCommand {
@@ -13,6 +16,49 @@ pub struct AddCommand {
     name: String,
     /// Source specification
     source: String,
+
+    /// Expected hash of the source archive (hex-encoded SHA-256 or Subresource Integrity format)
+    #[arg(long)]
+    integrity: Option<Integrity>,
+
+    /// Additional HTTP header to send when retrieving the source
+    #[arg(short = 'H', long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Source of a userContent.css to install alongside a single-file source
+    #[arg(long)]
+    user_content: Option<String>,
+
+    /// Directory within the source to install as the chrome directory (detected by default)
+    #[arg(long, value_parser = parse_chrome_dir)]
+    chrome_dir: Option<String>,
+}
+
+fn parse_header(s: &str) -> Result<(String, String)> {
+    let Some((name, value)) = s.split_once(':') else {
+        bail!("expected a header in the format `NAME: VALUE`");
+    };
+
+    let (name, value) = (name.trim(), value.trim());
+
+    HeaderName::from_bytes(name.as_bytes())?;
+    HeaderValue::from_str(value)?;
+
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+fn parse_chrome_dir(s: &str) -> Result<String> {
+    let s = s.trim_matches('/');
+
+    if s.is_empty()
+        || Path::new(s)
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        bail!("expected a relative path within the source");
+    }
+
+    Ok(s.to_owned())
 }
 
 impl super::Command for AddCommand {
@@ -25,6 +71,25 @@ impl super::Command for AddCommand {
 
         let parsed: ParsedSource = self.source.parse()?;
 
+        let user_content = if let Some(user_content) = &self.user_content {
+            if !parsed.is_single_file() {
+                bail!("a userContent.css source can only be added to a single-file source");
+            }
+
+            let parsed_user_content: ParsedSource = user_content.parse()?;
+            if !parsed_user_content.is_single_file() {
+                bail!("the userContent.css source must be a single stylesheet");
+            }
+
+            Some(if parsed_user_content.should_canonicalize() {
+                parsed_user_content.to_string()
+            } else {
+                user_content.clone()
+            })
+        } else {
+            None
+        };
+
         let new_userchrome = config::Userchrome {
             name: self.name.clone(),
             source: if parsed.should_canonicalize() {
@@ -32,7 +97,11 @@ impl super::Command for AddCommand {
             } else {
                 self.source.clone()
             },
-            configs: Vec::new(),
+            integrity: self.integrity.as_ref().map(|i| i.to_string()),
+            headers: self.headers.iter().cloned().collect(),
+            user_content,
+            chrome_dir: self.chrome_dir.clone(),
+            ..Default::default()
         };
 
         new_userchrome.print(false, config::PrintContext::Added);
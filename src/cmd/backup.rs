@@ -0,0 +1,123 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::path::Path;
+
+use anstream::println;
+use bytesize::ByteSize;
+use clap::{Parser, Subcommand};
+use eyre::{Result, eyre};
+use owo_colors::OwoColorize as _;
+
+use crate::{
+    backup::{self, MaxAge, PruneRules},
+    config, utils,
+};
+
+#[derive(Parser)]
+pub struct BackupCommand {
+    #[command(subcommand)]
+    command: BackupSubcommands,
+}
+
+#[derive(Subcommand)]
+enum BackupSubcommands {
+    /// List backups in the profile
+    List,
+
+    /// Restore a backup, backing up the file or directory it replaces
+    Restore {
+        /// Name of the backup, or `chrome`, `user.js` or `user-overrides.js` for the newest one
+        name: String,
+    },
+
+    /// Delete old backups
+    #[command(group = clap::ArgGroup::new("rules").required(true).multiple(true))]
+    Prune {
+        /// Number of backups to keep of each file or directory
+        #[arg(long, group = "rules")]
+        keep: Option<usize>,
+
+        /// Delete backups older than this (e.g. `12h`, `30d` or `2w`)
+        #[arg(long, group = "rules")]
+        older_than: Option<MaxAge>,
+
+        /// Only show which backups would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Deletes the backups in a profile that should be pruned according to `rules`.
+pub async fn prune(profile: &Path, rules: &PruneRules, dry_run: bool) -> Result<()> {
+    for backup in backup::prunable(profile, rules).await? {
+        if !dry_run {
+            backup::remove(&backup).await?;
+        }
+
+        println!(
+            "{} {} {}",
+            "-".red(),
+            backup.name().red(),
+            backup::format_age(backup.age()).dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+impl super::Command for BackupCommand {
+    async fn action(&self, global_options: &super::Cli) -> Result<()> {
+        let config = config::Config::read(&global_options.config).await?;
+        let profile = config
+            .profile
+            .as_ref()
+            .ok_or_else(|| eyre!("no profile configured"))?;
+
+        match &self.command {
+            BackupSubcommands::List => {
+                for backup in backup::discover(profile).await? {
+                    println!(
+                        "{} {} {} {}",
+                        "·".cyan(),
+                        backup.name().cyan(),
+                        backup::format_age(backup.age()).dimmed(),
+                        ByteSize::b(backup.size).dimmed()
+                    );
+                }
+            }
+
+            BackupSubcommands::Restore { name } => {
+                if !global_options.no_running_check {
+                    utils::check_firefox()?;
+                }
+
+                let backup = backup::find(profile, name).await?;
+                backup::restore(profile, &backup).await?;
+
+                println!(
+                    "{} {} {}",
+                    "*".blue(),
+                    backup.target.blue(),
+                    format!("restored from {}", backup.name()).dimmed()
+                );
+            }
+
+            BackupSubcommands::Prune {
+                keep,
+                older_than,
+                dry_run,
+            } => {
+                let rules = PruneRules {
+                    keep: *keep,
+                    max_age: *older_than,
+                };
+
+                prune(profile, &rules, *dry_run).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -2,10 +2,10 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use eyre::{Result, eyre};
 
-use crate::config;
+use crate::{config, prefs::PrefValue};
 
 #[derive(Parser)]
 pub struct ConfigCommand {
@@ -27,9 +27,9 @@ enum ConfigSubcommands {
         /// Config value
         value: String,
 
-        #[arg(short, long)]
-        /// Whether the value is a raw value or a string
-        raw: bool,
+        #[arg(short = 't', long = "type")]
+        /// Type of the value (inferred by default)
+        value_type: Option<ValueType>,
     },
 
     /// Unset a Firefox config
@@ -41,6 +41,31 @@ enum ConfigSubcommands {
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum ValueType {
+    Bool,
+    Int,
+    String,
+}
+
+impl ValueType {
+    fn parse(self, value: &str) -> Result<PrefValue> {
+        Ok(match self {
+            Self::Bool => PrefValue::Bool(
+                value
+                    .parse()
+                    .map_err(|_| eyre!("{value:?} is not a boolean"))?,
+            ),
+            Self::Int => PrefValue::Int(
+                value
+                    .parse()
+                    .map_err(|_| eyre!("{value:?} is not an integer"))?,
+            ),
+            Self::String => PrefValue::String(value.to_owned()),
+        })
+    }
+}
+
 impl super::Command for ConfigCommand {
     async fn action(&self, global_options: &super::Cli) -> Result<()> {
         match &self.command {
@@ -62,8 +87,13 @@ impl super::Command for ConfigCommand {
                 name,
                 key,
                 value,
-                raw,
+                value_type,
             } => {
+                let value = match value_type {
+                    Some(value_type) => value_type.parse(value)?,
+                    None => PrefValue::infer(value),
+                };
+
                 let mut config = config::Config::read(&global_options.config).await?;
 
                 let chrome = config
@@ -75,13 +105,11 @@ impl super::Command for ConfigCommand {
                 let existing = chrome.configs.iter_mut().find(|c| c.key == *key);
 
                 if let Some(existing) = existing {
-                    existing.value.clone_from(value);
-                    existing.raw.clone_from(raw);
+                    existing.value = value;
                 } else {
                     chrome.configs.push(config::UserchromeConfig {
                         key: key.clone(),
-                        value: value.clone(),
-                        raw: *raw,
+                        value,
                     });
                 }
 
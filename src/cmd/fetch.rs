@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{num::NonZeroUsize, sync::Arc};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use anstream::println;
+use clap::Parser;
+use eyre::{Result, bail};
+use owo_colors::OwoColorize as _;
+use temp_dir::TempDir;
+
+use crate::{config, lock, source::RetrieveOptions, switch, utils};
+
+#[derive(Parser)]
+pub struct FetchCommand {
+    /// Names of the userchromes to fetch (defaults to all userchromes)
+    names: Vec<String>,
+
+    /// Number of userchromes to fetch concurrently
+    #[arg(short, long, default_value = "4")]
+    jobs: NonZeroUsize,
+
+    /// Resolve the sources again and update the lockfile instead of fetching the locked revisions
+    #[arg(long)]
+    update_lock: bool,
+}
+
+impl super::Command for FetchCommand {
+    async fn action(&self, global_options: &super::Cli) -> Result<()> {
+        let config = config::Config::read(&global_options.config).await?;
+        utils::download::configure(&config.network)?;
+
+        let lock_path = lock::get_lock_path(&global_options.config);
+        let mut lock = lock::Lockfile::read(&lock_path).await?;
+
+        if let Some(name) = self
+            .names
+            .iter()
+            .find(|name| !config.userchromes.iter().any(|c| &c.name == *name))
+        {
+            bail!("no userchrome with name {name:?} found!");
+        }
+
+        let userchromes = config
+            .userchromes
+            .iter()
+            .filter(|c| self.names.is_empty() || self.names.contains(&c.name))
+            .collect::<Vec<_>>();
+
+        // progress is not shown since multiple downloads run at the same time
+        let options = RetrieveOptions {
+            offline: global_options.offline,
+            quiet: true,
+            limits: config.limits,
+            tokens: config.tokens.clone(),
+            ..Default::default()
+        };
+
+        let semaphore = Arc::new(Semaphore::new(self.jobs.get()));
+        let mut tasks = JoinSet::new();
+
+        for userchrome in &userchromes {
+            let userchrome = (*userchrome).clone();
+            let locked = lock.get(&userchrome).cloned();
+
+            let semaphore = semaphore.clone();
+            let options = options.clone();
+            let update_lock = self.update_lock;
+
+            tasks.spawn(async move {
+                let result = async {
+                    let _permit = semaphore.acquire_owned().await?;
+                    let temp_dir = TempDir::new()?;
+
+                    switch::retrieve_locked(
+                        &userchrome,
+                        temp_dir.path(),
+                        locked.as_ref(),
+                        update_lock,
+                        &options,
+                    )
+                    .await
+                }
+                .await;
+
+                (userchrome, result)
+            });
+        }
+
+        let mut failed = 0;
+
+        while let Some(joined) = tasks.join_next().await {
+            let (userchrome, result) = joined?;
+
+            match result {
+                Ok(locked) => {
+                    println!(
+                        "{} {} {}",
+                        "✓".green(),
+                        userchrome.name.green(),
+                        locked.resolved.dimmed()
+                    );
+
+                    lock.insert(locked);
+                }
+                Err(err) => {
+                    failed += 1;
+
+                    println!(
+                        "{} {} {}",
+                        "✗".red(),
+                        userchrome.name.red(),
+                        format!("{err:#}").dimmed()
+                    );
+                }
+            }
+        }
+
+        lock.write(&lock_path).await?;
+
+        if failed > 0 {
+            bail!(
+                "failed to fetch {failed} out of {} userchromes",
+                userchromes.len()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -11,8 +11,10 @@ use eyre::Result;
 use crate::config::get_default_config_path;
 
 mod add;
+mod backup;
 mod completions;
 mod config;
+mod fetch;
 mod list;
 mod preset;
 mod profile;
@@ -30,6 +32,14 @@ pub struct Cli {
     #[arg(short, long, default_value_os_t = get_default_config_path().unwrap(), value_hint = ValueHint::FilePath)]
     config: PathBuf,
 
+    /// Only use cached sources instead of accessing the network
+    #[arg(long)]
+    offline: bool,
+
+    /// Don't show download progress
+    #[arg(short, long)]
+    quiet: bool,
+
     /// Skip checking whether Firefox is running
     #[arg(long = "dangerous-no-running-check")]
     no_running_check: bool,
@@ -53,12 +63,16 @@ pub enum Commands {
     Switch(switch::SwitchCommand),
     /// Update userchrome currently in use
     Update(update::UpdateCommand),
+    /// Download and cache userchromes without installing them
+    Fetch(fetch::FetchCommand),
     /// Import a preset as a userchrome or list presets
     Preset(preset::PresetCommand),
     /// Configure Firefox profile or get current configured profile
     Profile(profile::ProfileCommand),
     /// Manage userchrome-linked configs
     Config(config::ConfigCommand),
+    /// Manage backups in the profile
+    Backup(backup::BackupCommand),
     /// Generate completions
     Completions(completions::CompletionCommand),
 }
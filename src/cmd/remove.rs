@@ -5,7 +5,7 @@
 use clap::Parser;
 use eyre::{Result, eyre};
 
-use crate::config;
+use crate::{config, lock};
 
 #[derive(Parser)]
 pub struct RemoveCommand {
@@ -29,6 +29,12 @@ impl super::Command for RemoveCommand {
             config.userchromes.remove(i);
             config.write(&global_options.config).await?;
 
+            let lock_path = lock::get_lock_path(&global_options.config);
+            let mut lock = lock::Lockfile::read(&lock_path).await?;
+            if lock.remove(&self.name) {
+                lock.write(&lock_path).await?;
+            }
+
             Ok(())
         } else {
             Err(eyre!(
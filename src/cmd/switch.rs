@@ -5,27 +5,43 @@
 use clap::Parser;
 use eyre::{Result, bail};
 
-use crate::{config, switch, utils};
+use crate::{config, lock, source::RetrieveOptions, switch, utils};
 
 #[derive(Parser)]
 pub struct SwitchCommand {
     /// Name of the userchrome to install (use `out` to uninstall the current userchrome, if any)
     name: String,
+
+    /// Resolve the source again and update the lockfile instead of installing the locked revision
+    #[arg(long)]
+    update_lock: bool,
 }
 
 impl super::Command for SwitchCommand {
     async fn action(&self, global_options: &super::Cli) -> Result<()> {
         let config = config::Config::read(&global_options.config).await?;
+        utils::download::configure(&config.network)?;
+
+        let lock_path = lock::get_lock_path(&global_options.config);
+        let mut lock = lock::Lockfile::read(&lock_path).await?;
 
         if !global_options.no_running_check {
             utils::check_firefox()?;
         }
 
+        let options = RetrieveOptions {
+            offline: global_options.offline,
+            quiet: global_options.quiet,
+            limits: config.limits,
+            tokens: config.tokens.clone(),
+            ..Default::default()
+        };
+
         if let Some(profile) = &config.profile {
             if self.name == "out" {
-                switch::switch(None, profile).await?;
+                switch::switch(None, profile, &mut lock, self.update_lock, &options).await?;
             } else if let Some(u) = config.userchromes.iter().find(|c| c.name == self.name) {
-                switch::switch(Some(u), profile).await?;
+                switch::switch(Some(u), profile, &mut lock, self.update_lock, &options).await?;
             } else {
                 bail!("no userchrome with name {:?} found!", self.name);
             }
@@ -33,6 +49,14 @@ impl super::Command for SwitchCommand {
             bail!("no profile configured");
         }
 
+        lock.write(&lock_path).await?;
+
+        if let Some(profile) = &config.profile
+            && !config.backups.is_default()
+        {
+            super::backup::prune(profile, &config.backups, false).await?;
+        }
+
         Ok(())
     }
 }
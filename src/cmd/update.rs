@@ -7,19 +7,35 @@ use std::fs;
 use clap::Parser;
 use eyre::{Result, bail};
 
-use crate::{config, switch, utils};
+use crate::{config, lock, source::RetrieveOptions, switch, utils};
 
 #[derive(Parser)]
-pub struct UpdateCommand {}
+pub struct UpdateCommand {
+    /// Resolve the source again and update the lockfile instead of installing the locked revision
+    #[arg(long)]
+    update_lock: bool,
+}
 
 impl super::Command for UpdateCommand {
     async fn action(&self, global_options: &super::Cli) -> Result<()> {
         let config = config::Config::read(&global_options.config).await?;
+        utils::download::configure(&config.network)?;
+
+        let lock_path = lock::get_lock_path(&global_options.config);
+        let mut lock = lock::Lockfile::read(&lock_path).await?;
 
         if !global_options.no_running_check {
             utils::check_firefox()?;
         }
 
+        let options = RetrieveOptions {
+            offline: global_options.offline,
+            quiet: global_options.quiet,
+            limits: config.limits,
+            tokens: config.tokens.clone(),
+            ..Default::default()
+        };
+
         if let Some(profile) = &config.profile {
             let current = fs::read_to_string(profile.join("chrome").join(".nyoom-chrome-name"))
                 .map(|s| s.trim().to_owned())
@@ -30,7 +46,7 @@ impl super::Command for UpdateCommand {
                 .iter()
                 .find(|c| Some(&c.name) == current.as_ref())
             {
-                switch::switch(Some(u), profile).await?;
+                switch::switch(Some(u), profile, &mut lock, self.update_lock, &options).await?;
             } else {
                 bail!("no installed userchrome found!");
             }
@@ -38,6 +54,14 @@ impl super::Command for UpdateCommand {
             bail!("no profile configured");
         }
 
+        lock.write(&lock_path).await?;
+
+        if let Some(profile) = &config.profile
+            && !config.backups.is_default()
+        {
+            super::backup::prune(profile, &config.backups, false).await?;
+        }
+
         Ok(())
     }
 }
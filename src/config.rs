@@ -4,16 +4,21 @@
 
 use eyre::{Result, eyre};
 use std::{
+    collections::BTreeMap,
     fmt,
     path::{Path, PathBuf},
 };
 use tokio::fs;
 
-use anstream::println;
+use anstream::{eprintln, println};
+use bytesize::ByteSize;
 use etcetera::AppStrategy as _;
 use owo_colors::OwoColorize as _;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
 
+use crate::{backup::PruneRules, prefs::PrefValue, utils::integrity::Integrity};
+
 fn strategy() -> Result<impl etcetera::AppStrategy> {
     etcetera::choose_app_strategy(etcetera::AppStrategyArgs {
         top_level_domain: "dev.ryanccn".to_owned(),
@@ -27,26 +32,147 @@ pub fn get_default_config_path() -> Result<PathBuf> {
     Ok(strategy()?.config_dir().join("nyoom.toml"))
 }
 
+pub fn get_cache_dir() -> Result<PathBuf> {
+    Ok(strategy()?.cache_dir())
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(from = "UserchromeConfigRepr")]
 pub struct UserchromeConfig {
     pub key: String,
-    pub value: String,
-    pub raw: bool,
+    pub value: PrefValue,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+/// The stored format of a [`UserchromeConfig`], which also accepts string values with the
+/// `raw` flag from older configs.
+#[derive(Deserialize)]
+struct UserchromeConfigRepr {
+    key: String,
+    value: PrefValue,
+    /// Whether a string value is a JavaScript literal
+    raw: Option<bool>,
+}
+
+impl From<UserchromeConfigRepr> for UserchromeConfig {
+    fn from(repr: UserchromeConfigRepr) -> Self {
+        let value = match (repr.value, repr.raw) {
+            (PrefValue::String(value), Some(true)) => PrefValue::parse_literal(&value)
+                .unwrap_or_else(|err| {
+                    // expressions can't be represented anymore, so keep the config usable
+                    eprintln!(
+                        "{} could not migrate raw value of {:?}, keeping it as a string: {err}",
+                        "warning:".yellow(),
+                        repr.key
+                    );
+
+                    PrefValue::String(value)
+                }),
+            (value, _) => value,
+        };
+
+        Self {
+            key: repr.key,
+            value,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct Userchrome {
     pub name: String,
     pub source: String,
 
+    /// Expected hex-encoded SHA-256 of the source archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    /// Expected hash of the source archive in Subresource Integrity format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+
+    /// Additional HTTP headers sent when retrieving the source
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// Source of a `userContent.css` installed alongside a single-file source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_content: Option<String>,
+
+    /// Directory within the source to install as the `chrome` directory, instead of detecting it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chrome_dir: Option<String>,
+
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub configs: Vec<UserchromeConfig>,
 }
 
+/// Limits that protect against archives that expand to unreasonable sizes when extracted.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ExtractionLimits {
+    /// Maximum total size of the files in an archive
+    pub max_size: ByteSize,
+    /// Maximum number of entries in an archive
+    pub max_entries: u64,
+}
+
+impl ExtractionLimits {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl Default for ExtractionLimits {
+    fn default() -> Self {
+        Self {
+            max_size: ByteSize::gb(1),
+            max_entries: 100_000,
+        }
+    }
+}
+
+/// Settings for the HTTP client used to retrieve sources.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// URL of a proxy that all requests are sent through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Hosts that bypass the proxy
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub no_proxy: Vec<String>,
+    /// Paths to PEM-encoded CA certificates to trust in addition to the built-in roots
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ca_certificates: Vec<PathBuf>,
+    /// Custom `User-Agent` header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+impl NetworkConfig {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct Config {
     pub profile: Option<PathBuf>,
 
+    #[serde(default, skip_serializing_if = "ExtractionLimits::is_default")]
+    pub limits: ExtractionLimits,
+
+    #[serde(default, skip_serializing_if = "NetworkConfig::is_default")]
+    pub network: NetworkConfig,
+
+    /// Rules for pruning backups automatically after switching
+    #[serde(default, skip_serializing_if = "PruneRules::is_default")]
+    pub backups: PruneRules,
+
+    /// Access tokens for forges, keyed by host
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tokens: BTreeMap<String, String>,
+
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub userchromes: Vec<Userchrome>,
 }
@@ -76,17 +202,7 @@ impl Config {
 
 impl fmt::Display for UserchromeConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}: {}{}",
-            self.key.magenta(),
-            self.value,
-            if self.raw {
-                " (raw)".dimmed().to_string()
-            } else {
-                String::new()
-            }
-        )
+        write!(f, "{}: {}", self.key.magenta(), self.value)
     }
 }
 
@@ -100,6 +216,32 @@ pub enum PrintContext {
 }
 
 impl Userchrome {
+    pub fn integrity(&self) -> Result<Vec<Integrity>> {
+        let mut ret = Vec::new();
+
+        if let Some(sha256) = &self.sha256 {
+            ret.push(Integrity::sha256_hex(sha256)?);
+        }
+
+        if let Some(integrity) = &self.integrity {
+            ret.push(integrity.parse()?);
+        }
+
+        Ok(ret)
+    }
+
+    pub fn headers(&self) -> Result<HeaderMap> {
+        self.headers
+            .iter()
+            .map(|(name, value)| {
+                Ok((
+                    HeaderName::from_bytes(name.as_bytes())?,
+                    HeaderValue::from_str(value)?,
+                ))
+            })
+            .collect()
+    }
+
     pub fn print(&self, short: bool, context: PrintContext) {
         match context {
             PrintContext::Normal => {
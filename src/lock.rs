@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use eyre::{Result, eyre};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Userchrome;
+
+pub fn get_lock_path(config_path: &Path) -> PathBuf {
+    config_path.with_extension("lock")
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LockedUserchrome {
+    pub name: String,
+    pub source: String,
+    pub resolved: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_content_resolved: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_content_sha256: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Lockfile {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub userchromes: Vec<LockedUserchrome>,
+}
+
+impl Lockfile {
+    pub async fn read(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path).await {
+            Ok(s) => toml::from_str(&s).map_err(|e| e.into()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Lockfile::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        fs::create_dir_all(
+            path.parent()
+                .ok_or_else(|| eyre!("could not obtain parent directory of lockfile"))?,
+        )
+        .await?;
+
+        let serialized = toml::to_string_pretty(&self)?;
+        fs::write(path, serialized).await?;
+
+        Ok(())
+    }
+
+    /// Returns the locked entry for a userchrome, if one exists and was locked from the same sources.
+    pub fn get(&self, userchrome: &Userchrome) -> Option<&LockedUserchrome> {
+        self.userchromes.iter().find(|l| {
+            l.name == userchrome.name
+                && l.source == userchrome.source
+                && l.user_content == userchrome.user_content
+        })
+    }
+
+    pub fn insert(&mut self, locked: LockedUserchrome) {
+        self.remove(&locked.name);
+        self.userchromes.push(locked);
+        self.userchromes.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.userchromes.len();
+        self.userchromes.retain(|l| l.name != name);
+        self.userchromes.len() != len
+    }
+}
@@ -6,8 +6,11 @@ use clap::Parser;
 use cmd::{Cli, Command};
 use eyre::Result;
 
+mod backup;
 mod cmd;
 mod config;
+mod lock;
+mod prefs;
 mod presets;
 mod source;
 mod switch;
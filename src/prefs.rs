@@ -0,0 +1,521 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+
+use eyre::{Result, bail, eyre};
+use serde::{Deserialize, Serialize, de};
+
+/// Line that starts the block of prefs managed by nyoom in a user file.
+pub const START_LINE: &str = "/** nyoom-managed config; do not edit */";
+/// Line that ends the block of prefs managed by nyoom in a user file.
+pub const END_LINE: &str = "/** end of nyoom-managed config */";
+
+/// Finds the block of prefs managed by nyoom in the lines of a user file, returning the indices
+/// of its start and end lines.
+///
+/// Markers that don't form a pair, like an end line without a preceding start line, are ignored.
+pub fn managed_block(lines: &[&str]) -> Option<(usize, usize)> {
+    let mut start = None;
+
+    for (idx, line) in lines.iter().enumerate() {
+        if *line == START_LINE {
+            start = Some(idx);
+        } else if *line == END_LINE
+            && let Some(start) = start
+        {
+            return Some((start, idx));
+        }
+    }
+
+    None
+}
+
+/// The function used to set a pref, which determines its precedence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PrefKind {
+    /// `pref`, which sets the default value
+    Default,
+    /// `user_pref` or `sticky_pref`, which set the user value
+    User,
+    /// `lockPref`, which sets a value that cannot be changed
+    Locked,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PrefValue {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+impl PrefValue {
+    /// Infers the type of a value given on the command line, treating it as a string
+    /// unless it is a boolean or an integer.
+    ///
+    /// Only integers in canonical form are inferred, so that values like `0123` or `+1` are
+    /// kept as written.
+    pub fn infer(s: &str) -> Self {
+        match s {
+            "true" => Self::Bool(true),
+            "false" => Self::Bool(false),
+            s => s
+                .parse::<i64>()
+                .ok()
+                .filter(|n| n.to_string() == s)
+                .map_or_else(|| Self::String(s.to_owned()), Self::Int),
+        }
+    }
+
+    /// Parses a value written as a JavaScript literal.
+    pub fn parse_literal(s: &str) -> Result<Self> {
+        let mut lexer = Lexer {
+            chars: s.chars().peekable(),
+            line: 1,
+        };
+
+        match (
+            lexer
+                .next_token()?
+                .and_then(|(token, _)| token.into_value()),
+            lexer.next_token()?,
+        ) {
+            (Some(value), None) => Ok(value),
+            _ => bail!("{s:?} is not a boolean, integer or string literal"),
+        }
+    }
+}
+
+impl Serialize for PrefValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Bool(value) => serializer.serialize_bool(*value),
+            Self::Int(value) => serializer.serialize_i64(*value),
+            Self::String(value) => serializer.serialize_str(value),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PrefValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = PrefValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a boolean, integer or string")
+            }
+
+            fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+                Ok(PrefValue::Bool(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(PrefValue::Int(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                i64::try_from(v)
+                    .map(PrefValue::Int)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(PrefValue::String(v.to_owned()))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl fmt::Display for PrefValue {
+    /// Formats the value as a JavaScript literal.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Int(value) => write!(f, "{value}"),
+            Self::String(value) => {
+                f.write_str("\"")?;
+
+                for c in value.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        '\t' => f.write_str("\\t")?,
+                        c if c.is_control() => write!(f, "\\u{:04x}", u32::from(c))?,
+                        c => write!(f, "{c}")?,
+                    }
+                }
+
+                f.write_str("\"")
+            }
+        }
+    }
+}
+
+/// A pref statement in a `user.js` file.
+#[derive(Clone, Debug)]
+pub struct Pref {
+    pub kind: PrefKind,
+    pub key: String,
+    pub value: PrefValue,
+    /// Line number (starting at 1) that the statement starts on
+    pub line: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    String(String),
+    Int(i64),
+    Punct(char),
+}
+
+impl Token {
+    fn into_value(self) -> Option<PrefValue> {
+        match self {
+            Self::String(value) => Some(PrefValue::String(value)),
+            Self::Int(value) => Some(PrefValue::Int(value)),
+            Self::Ident(ident) if ident == "true" => Some(PrefValue::Bool(true)),
+            Self::Ident(ident) if ident == "false" => Some(PrefValue::Bool(false)),
+            _ => None,
+        }
+    }
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl Lexer<'_> {
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+        }
+
+        Some(c)
+    }
+
+    fn skip_line(&mut self) {
+        while self.chars.peek().is_some_and(|c| *c != '\n') {
+            self.bump();
+        }
+    }
+
+    /// Skips whitespace and comments (`//`, `/* */` and `#`).
+    fn skip_trivia(&mut self) -> Result<()> {
+        loop {
+            match self.chars.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('#') => self.skip_line(),
+                Some('/') => {
+                    let start = self.line;
+                    self.bump();
+
+                    match self.bump() {
+                        Some('/') => self.skip_line(),
+                        Some('*') => loop {
+                            match self.bump() {
+                                Some('*') if self.chars.peek() == Some(&'/') => {
+                                    self.bump();
+                                    break;
+                                }
+                                Some(_) => {}
+                                None => bail!("unterminated comment starting on line {start}"),
+                            }
+                        },
+                        _ => bail!("unexpected '/' on line {start}"),
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn string(&mut self, quote: char) -> Result<String> {
+        let start = self.line;
+        let mut value = String::new();
+
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(value),
+                Some('\\') => {
+                    let escaped = match self.bump() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('x') => self.hex_escape(2)?,
+                        Some('u') => self.hex_escape(4)?,
+                        Some(c) => c,
+                        None => break,
+                    };
+
+                    value.push(escaped);
+                }
+                Some(c) => value.push(c),
+                None => break,
+            }
+        }
+
+        bail!("unterminated string starting on line {start}")
+    }
+
+    fn hex_escape(&mut self, len: usize) -> Result<char> {
+        let digits = (0..len).filter_map(|_| self.bump()).collect::<String>();
+
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| eyre!("invalid escape sequence on line {}", self.line))
+    }
+
+    /// Returns the next token along with the line it starts on.
+    fn next_token(&mut self) -> Result<Option<(Token, usize)>> {
+        self.skip_trivia()?;
+
+        let line = self.line;
+        let Some(&c) = self.chars.peek() else {
+            return Ok(None);
+        };
+
+        let token = match c {
+            '"' | '\'' => {
+                self.bump();
+                Token::String(self.string(c)?)
+            }
+            '-' | '+' | '0'..='9' => {
+                let mut number = String::new();
+
+                while let Some(&c) = self.chars.peek()
+                    && (c.is_ascii_digit() || (number.is_empty() && (c == '-' || c == '+')))
+                {
+                    number.push(c);
+                    self.bump();
+                }
+
+                Token::Int(
+                    number
+                        .parse()
+                        .map_err(|_| eyre!("invalid number {number:?} on line {line}"))?,
+                )
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+
+                while let Some(&c) = self.chars.peek()
+                    && (c.is_ascii_alphanumeric() || c == '_')
+                {
+                    ident.push(c);
+                    self.bump();
+                }
+
+                Token::Ident(ident)
+            }
+            '(' | ')' | ',' | ';' => {
+                self.bump();
+                Token::Punct(c)
+            }
+            c => bail!("unexpected {c:?} on line {line}"),
+        };
+
+        Ok(Some((token, line)))
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.next_token()? {
+            Some((Token::Punct(c), _)) if c == expected => Ok(()),
+            _ => bail!("expected '{expected}' on line {}", self.line),
+        }
+    }
+}
+
+/// Parses the pref statements of a `user.js` file.
+pub fn parse(contents: &str) -> Result<Vec<Pref>> {
+    let mut lexer = Lexer {
+        chars: contents.chars().peekable(),
+        line: 1,
+    };
+
+    let mut prefs = Vec::new();
+
+    while let Some((token, line)) = lexer.next_token()? {
+        let kind = match token {
+            Token::Ident(ident) if ident == "pref" => PrefKind::Default,
+            Token::Ident(ident) if ident == "user_pref" || ident == "sticky_pref" => PrefKind::User,
+            Token::Ident(ident) if ident == "lockPref" => PrefKind::Locked,
+            _ => bail!("expected a pref statement on line {line}"),
+        };
+
+        lexer.expect('(')?;
+
+        let Some((Token::String(key), _)) = lexer.next_token()? else {
+            bail!("expected a pref name on line {}", lexer.line);
+        };
+
+        lexer.expect(',')?;
+
+        let Some(value) = lexer
+            .next_token()?
+            .and_then(|(token, _)| token.into_value())
+        else {
+            bail!("expected a value for {key:?} on line {}", lexer.line);
+        };
+
+        lexer.expect(')')?;
+        lexer.expect(';')?;
+
+        prefs.push(Pref {
+            kind,
+            key,
+            value,
+            line,
+        });
+    }
+
+    Ok(prefs)
+}
+
+/// Returns the assignment of a pref that takes effect, i.e. the last one with the highest precedence.
+pub fn effective<'a>(assignments: &[&'a Pref]) -> Option<&'a Pref> {
+    assignments.iter().copied().max_by_key(|pref| pref.kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pref(kind: PrefKind, value: i64) -> Pref {
+        Pref {
+            kind,
+            key: "a".to_owned(),
+            value: PrefValue::Int(value),
+            line: 1,
+        }
+    }
+
+    #[test]
+    fn inferred_values() {
+        assert_eq!(PrefValue::infer("true"), PrefValue::Bool(true));
+        assert_eq!(PrefValue::infer("-12"), PrefValue::Int(-12));
+
+        for s in ["0123", "+1", "-0", "1e3", "True", ""] {
+            assert_eq!(PrefValue::infer(s), PrefValue::String(s.to_owned()));
+        }
+    }
+
+    #[test]
+    fn statements() {
+        let prefs = parse(
+            r#"
+            // a line comment
+            # a hash comment
+            /* a block
+               comment */ user_pref("a.b", true);
+            pref(
+                'c',
+                -42
+            ); lockPref("d", "e") ;
+            sticky_pref("f", false); // trailing comment
+            "#,
+        )
+        .unwrap();
+
+        let summary = prefs
+            .iter()
+            .map(|p| (p.kind, p.key.as_str(), p.value.clone(), p.line))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            [
+                (PrefKind::User, "a.b", PrefValue::Bool(true), 5),
+                (PrefKind::Default, "c", PrefValue::Int(-42), 6),
+                (PrefKind::Locked, "d", PrefValue::String("e".to_owned()), 9),
+                (PrefKind::User, "f", PrefValue::Bool(false), 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        let prefs = parse(r#"user_pref("a", "\x41\u00e9\n\"\'\\");"#).unwrap();
+        assert_eq!(
+            prefs[0].value,
+            PrefValue::String("A\u{e9}\n\"'\\".to_owned())
+        );
+
+        // values are written back in a form that parses to the same value
+        let written = format!("user_pref(\"a\", {});", prefs[0].value);
+        assert_eq!(parse(&written).unwrap()[0].value, prefs[0].value);
+
+        assert!(parse(r#"user_pref("a", "\xZZ");"#).is_err());
+        assert!(parse(r#"user_pref("a", "\u12");"#).is_err());
+    }
+
+    #[test]
+    fn invalid_statements() {
+        for contents in [
+            r#"user_pref("a", true)"#,
+            r#"user_pref("a", window.foo);"#,
+            r#"user_pref("a", "b);"#,
+            r#"foo("a", true);"#,
+            "/* unterminated",
+        ] {
+            assert!(parse(contents).is_err(), "{contents:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let default = pref(PrefKind::Default, 1);
+        let user = pref(PrefKind::User, 2);
+        let later_user = pref(PrefKind::User, 3);
+        let locked = pref(PrefKind::Locked, 4);
+
+        let value = |assignments: &[&Pref]| effective(assignments).map(|p| p.value.clone());
+
+        assert_eq!(value(&[]), None);
+        assert_eq!(value(&[&default, &user]), Some(PrefValue::Int(2)));
+        assert_eq!(value(&[&user, &later_user]), Some(PrefValue::Int(3)));
+        assert_eq!(
+            value(&[&locked, &user, &later_user, &default]),
+            Some(PrefValue::Int(4))
+        );
+    }
+
+    #[test]
+    fn managed_blocks() {
+        assert_eq!(
+            managed_block(&["a", START_LINE, "b", END_LINE, "c"]),
+            Some((1, 3))
+        );
+
+        // an end line before the start line doesn't form a block
+        assert_eq!(managed_block(&[END_LINE, "a", START_LINE, "b"]), None);
+        assert_eq!(
+            managed_block(&[END_LINE, START_LINE, "a", END_LINE]),
+            Some((1, 3))
+        );
+
+        // the start line closest to the end line wins
+        assert_eq!(
+            managed_block(&[START_LINE, "a", START_LINE, END_LINE]),
+            Some((2, 3))
+        );
+
+        assert_eq!(managed_block(&["a", START_LINE]), None);
+
+        // markers are comments, so a file with reversed markers still parses
+        let contents = [END_LINE, r#"user_pref("a", 1);"#, START_LINE].join("\n");
+        assert_eq!(parse(&contents).unwrap()[0].line, 2);
+    }
+}
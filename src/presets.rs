@@ -4,7 +4,10 @@
 
 use std::sync::LazyLock;
 
-use crate::config::{Userchrome, UserchromeConfig};
+use crate::{
+    config::{Userchrome, UserchromeConfig},
+    prefs::PrefValue,
+};
 
 pub static PRESETS: LazyLock<Vec<Userchrome>> = LazyLock::new(|| {
     vec![
@@ -14,35 +17,30 @@ pub static PRESETS: LazyLock<Vec<Userchrome>> = LazyLock::new(|| {
             configs: vec![
                 UserchromeConfig {
                     key: "svg.context-properties.content.enabled".to_owned(),
-                    value: "true".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(true),
                 },
                 UserchromeConfig {
                     key: "layout.css.color-mix.enabled".to_owned(),
-                    value: "true".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(true),
                 },
                 UserchromeConfig {
                     key: "layout.css.light-dark.enabled".to_owned(),
-                    value: "true".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(true),
                 },
                 UserchromeConfig {
                     key: "widget.macos.native-context-menus".to_owned(),
-                    value: "false".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(false),
                 },
                 UserchromeConfig {
                     key: "browser.tabs.tabMinWidth".to_owned(),
-                    value: "66".to_owned(),
-                    raw: true,
+                    value: PrefValue::Int(66),
                 },
                 UserchromeConfig {
                     key: "browser.tabs.tabClipWidth".to_owned(),
-                    value: "86".to_owned(),
-                    raw: true,
+                    value: PrefValue::Int(86),
                 },
             ],
+            ..Default::default()
         },
         Userchrome {
             name: "shyfox".to_owned(),
@@ -50,35 +48,30 @@ pub static PRESETS: LazyLock<Vec<Userchrome>> = LazyLock::new(|| {
             configs: vec![
                 UserchromeConfig {
                     key: "svg.context-properties.content.enabled".to_owned(),
-                    value: "true".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(true),
                 },
                 UserchromeConfig {
                     key: "layout.css.has-selector.enabled".to_owned(),
-                    value: "true".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(true),
                 },
                 UserchromeConfig {
                     key: "browser.urlbar.suggest.calculator".to_owned(),
-                    value: "true".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(true),
                 },
                 UserchromeConfig {
                     key: "browser.urlbar.unitConversion.enabled".to_owned(),
-                    value: "true".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(true),
                 },
                 UserchromeConfig {
                     key: "browser.urlbar.trimHttps".to_owned(),
-                    value: "true".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(true),
                 },
                 UserchromeConfig {
                     key: "browser.urlbar.trimURLs".to_owned(),
-                    value: "true".to_owned(),
-                    raw: true,
+                    value: PrefValue::Bool(true),
                 },
             ],
+            ..Default::default()
         },
     ]
 });
@@ -3,36 +3,539 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
+    collections::BTreeMap,
     fmt::Display,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     str::FromStr,
     sync::LazyLock,
 };
 
-use eyre::{Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use eyre::{Result, bail, eyre};
 use regex::Regex;
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use temp_dir::TempDir;
 use url::Url;
 
-use crate::utils;
+use crate::{
+    config::ExtractionLimits,
+    utils::{self, download::RequestBuilderExt as _, integrity::Integrity},
+};
 
 static GITHUB_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"github:(?P<repo>([\w\-_]+)/([\w\-_]+))(#(?P<ref>[\w\-_]+))?").unwrap()
+    Regex::new(r"^github:(?P<repo>([\w\-.]+)/([\w\-.]+))(#(?P<ref>[^#]+))?$").unwrap()
 });
 
 static CODEBERG_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"codeberg:(?P<repo>([\w\-_]+)/([\w\-_]+))(#(?P<ref>[\w\-_]+))?").unwrap()
+    Regex::new(r"^codeberg:(?P<repo>([\w\-.]+)/([\w\-.]+))(#(?P<ref>[^#]+))?$").unwrap()
+});
+
+static FORGEJO_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(forgejo|gitea):(?P<host>[\w\-.]+(:\d+)?)/(?P<repo>([\w\-.]+)/([\w\-.]+))(#(?P<ref>[^#]+))?$",
+    )
+    .unwrap()
+});
+
+static GITLAB_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^gitlab:(?P<repo>[\w\-.]+(:\d+)?/[\w\-./]+)(#(?P<ref>[^#]+))?$").unwrap()
 });
 
-static GITLAB_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"gitlab:(?P<repo>[\w\-_/]+)(#(?P<ref>[\w\-_]+))?").unwrap());
+static SOURCEHUT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^sourcehut:(?P<repo>(~[\w\-.]+)/([\w\-.]+))(#(?P<ref>[^#]+))?$").unwrap()
+});
+
+static BITBUCKET_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^bitbucket:(?P<repo>([\w\-.]+)/([\w\-.]+))(#(?P<ref>[^#]+))?$").unwrap()
+});
+
+static GITHUB_RELEASE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^github-release:(?P<repo>([\w\-.]+)/([\w\-.]+))@(?P<tag>[^/@]+)/(?P<asset>[^/]+)$")
+        .unwrap()
+});
+
+static FORGEJO_RELEASE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(forgejo|gitea)-release:(?P<host>[\w\-.]+(:\d+)?)/(?P<repo>([\w\-.]+)/([\w\-.]+))@(?P<tag>[^/@]+)/(?P<asset>[^/]+)$",
+    )
+    .unwrap()
+});
+
+static HOST_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[\w\-]+(\.[\w\-]+)+(:\d+)?$").unwrap());
+
+const GITLAB_DEFAULT_HOST: &str = "gitlab.com";
+
+/// The tag of a release source that refers to the latest release.
+const LATEST_RELEASE: &str = "latest";
+
+// like Git itself, reject names starting with `-`, which could be mistaken for options
+static REF_NAME_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[\w./][\w\-./]*$").unwrap());
+
+static COMMIT_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[0-9a-fA-F]{7,40}$").unwrap());
+
+/// A Git revision in a forge source specification.
+///
+/// Plain refs are resolved by the forge, which accepts branches, tags and commit SHAs alike.
+/// The `branch:`, `tag:` and `commit:` prefixes can be used to disambiguate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GitRef {
+    Auto(String),
+    Branch(String),
+    Tag(String),
+    Commit(String),
+}
+
+impl GitRef {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Auto(name) | Self::Branch(name) | Self::Tag(name) | Self::Commit(name) => name,
+        }
+    }
+}
+
+impl Default for GitRef {
+    fn default() -> Self {
+        Self::Auto("main".to_owned())
+    }
+}
+
+impl Display for GitRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto(name) => write!(f, "{name}"),
+            Self::Branch(name) => write!(f, "branch:{name}"),
+            Self::Tag(name) => write!(f, "tag:{name}"),
+            Self::Commit(sha) => write!(f, "commit:{sha}"),
+        }
+    }
+}
+
+impl FromStr for GitRef {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, name) = s.split_once(':').unwrap_or(("", s));
+
+        if !REF_NAME_REGEX.is_match(name) || name.contains("..") {
+            bail!("invalid ref: {s:?}");
+        }
+
+        Ok(match kind {
+            "" => Self::Auto(name.to_owned()),
+            "branch" => Self::Branch(name.to_owned()),
+            "tag" => Self::Tag(name.to_owned()),
+            "commit" => {
+                if !COMMIT_REGEX.is_match(name) {
+                    bail!("invalid commit SHA: {name:?}");
+                }
+
+                Self::Commit(name.to_ascii_lowercase())
+            }
+            _ => bail!("invalid ref kind {kind:?} (expected `branch`, `tag` or `commit`)"),
+        })
+    }
+}
+
+/// Parses a `ref[:subdir]` revision specification, where both parts are optional.
+fn parse_revision(s: &str) -> Result<(Option<GitRef>, Option<String>)> {
+    let kind_len = ["branch:", "tag:", "commit:"]
+        .iter()
+        .find(|k| s.starts_with(*k))
+        .map_or(0, |k| k.len());
+
+    let (r#ref, subdir) = match s[kind_len..].find(':') {
+        Some(idx) => (&s[..kind_len + idx], Some(&s[kind_len + idx + 1..])),
+        None => (s, None),
+    };
+
+    let r#ref = if r#ref.is_empty() {
+        None
+    } else {
+        Some(r#ref.parse()?)
+    };
+
+    let subdir = subdir
+        .map(|subdir| subdir.trim_matches('/'))
+        .filter(|subdir| !subdir.is_empty())
+        .map(|subdir| {
+            if Path::new(subdir)
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+            {
+                bail!("invalid subdirectory: {subdir:?}");
+            }
+
+            Ok(subdir.to_owned())
+        })
+        .transpose()?;
+
+    Ok((r#ref, subdir))
+}
+
+fn fmt_revision(r#ref: Option<&GitRef>, subdir: Option<&str>) -> String {
+    match (r#ref, subdir) {
+        (Some(r#ref), Some(subdir)) => format!("#{ref}:{subdir}"),
+        (Some(r#ref), None) => format!("#{ref}"),
+        (None, Some(subdir)) => format!("#:{subdir}"),
+        (None, None) => String::new(),
+    }
+}
+
+async fn resolve_github(repo: &str, r#ref: &GitRef, headers: &HeaderMap) -> Result<String> {
+    let path = match r#ref {
+        GitRef::Auto(name) | GitRef::Commit(name) => name.to_owned(),
+        GitRef::Branch(name) => format!("heads/{name}"),
+        GitRef::Tag(name) => format!("tags/{name}"),
+    };
+
+    let sha = utils::download::client()
+        .get(format!(
+            "https://api.github.com/repos/{repo}/commits/{path}"
+        ))
+        .headers(headers.clone())
+        .header(header::ACCEPT, "application/vnd.github.sha")
+        .send_with_retry()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    Ok(sha.trim().to_owned())
+}
+
+/// Fetches `url` with `headers` and deserializes the JSON response.
+async fn get_json<T: serde::de::DeserializeOwned>(url: Url, headers: &HeaderMap) -> Result<T> {
+    Ok(utils::download::client()
+        .get(url)
+        .headers(headers.clone())
+        .send_with_retry()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+async fn resolve_forgejo(
+    host: &str,
+    repo: &str,
+    r#ref: &GitRef,
+    headers: &HeaderMap,
+) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Commit {
+        sha: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Branch {
+        commit: BranchCommit,
+    }
+
+    #[derive(Deserialize)]
+    struct BranchCommit {
+        id: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Tag {
+        commit: Commit,
+    }
+
+    let mut url: Url = format!("https://{host}/api/v1/repos/{repo}").parse()?;
+
+    {
+        let mut segments = url
+            .path_segments_mut()
+            .map_err(|()| eyre!("could not construct Forgejo API URL"))?;
+
+        match r#ref {
+            GitRef::Branch(name) => segments.push("branches").extend(name.split('/')),
+            GitRef::Tag(name) => segments.push("tags").extend(name.split('/')),
+            GitRef::Auto(_) | GitRef::Commit(_) => segments.push("commits"),
+        };
+    }
+
+    match r#ref {
+        GitRef::Branch(_) => return Ok(get_json::<Branch>(url, headers).await?.commit.id),
+        GitRef::Tag(_) => return Ok(get_json::<Tag>(url, headers).await?.commit.sha),
+        GitRef::Auto(_) | GitRef::Commit(_) => {}
+    }
+
+    url.query_pairs_mut()
+        .append_pair("sha", r#ref.name())
+        .append_pair("limit", "1")
+        .append_pair("stat", "false")
+        .append_pair("verification", "false")
+        .append_pair("files", "false");
+
+    get_json::<Vec<Commit>>(url, headers)
+        .await?
+        .into_iter()
+        .next()
+        .map(|c| c.sha)
+        .ok_or_else(|| eyre!("could not resolve {ref} in {host}/{repo}"))
+}
+
+async fn resolve_gitlab(
+    host: &str,
+    repo: &str,
+    r#ref: &GitRef,
+    headers: &HeaderMap,
+) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Commit {
+        id: String,
+    }
+
+    /// A branch or a tag.
+    #[derive(Deserialize)]
+    struct Ref {
+        commit: Commit,
+    }
+
+    let mut url: Url = format!("https://{host}/api/v4/projects").parse()?;
+
+    {
+        let mut segments = url
+            .path_segments_mut()
+            .map_err(|()| eyre!("could not construct GitLab API URL"))?;
+        segments.extend([repo, "repository"]);
+
+        match r#ref {
+            GitRef::Branch(name) => segments.extend(["branches", name]),
+            GitRef::Tag(name) => segments.extend(["tags", name]),
+            GitRef::Auto(name) | GitRef::Commit(name) => segments.extend(["commits", name]),
+        };
+    }
+
+    Ok(match r#ref {
+        GitRef::Branch(_) | GitRef::Tag(_) => get_json::<Ref>(url, headers).await?.commit.id,
+        GitRef::Auto(_) | GitRef::Commit(_) => get_json::<Commit>(url, headers).await?.id,
+    })
+}
+
+async fn resolve_bitbucket(repo: &str, r#ref: &GitRef, headers: &HeaderMap) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Commit {
+        hash: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Commits {
+        values: Vec<Commit>,
+    }
+
+    /// A branch or a tag.
+    #[derive(Deserialize)]
+    struct Ref {
+        target: Commit,
+    }
+
+    let mut url: Url = format!("https://api.bitbucket.org/2.0/repositories/{repo}").parse()?;
+
+    {
+        let mut segments = url
+            .path_segments_mut()
+            .map_err(|()| eyre!("could not construct Bitbucket API URL"))?;
+
+        match r#ref {
+            GitRef::Branch(name) => segments.extend(["refs", "branches", name]),
+            GitRef::Tag(name) => segments.extend(["refs", "tags", name]),
+            GitRef::Auto(name) | GitRef::Commit(name) => segments.extend(["commits", name]),
+        };
+    }
+
+    if let GitRef::Branch(_) | GitRef::Tag(_) = r#ref {
+        return Ok(get_json::<Ref>(url, headers).await?.target.hash);
+    }
+
+    url.query_pairs_mut().append_pair("pagelen", "1");
+
+    get_json::<Commits>(url, headers)
+        .await?
+        .values
+        .into_iter()
+        .next()
+        .map(|c| c.hash)
+        .ok_or_else(|| eyre!("could not resolve {ref} in bitbucket.org/{repo}"))
+}
+
+/// An asset of a release.
+#[derive(Deserialize, Debug)]
+struct ReleaseAsset {
+    name: String,
+    /// API endpoint of the asset, which is only provided by GitHub
+    url: Option<String>,
+}
+
+/// Resolves a release of a repository (or its latest release) and its only asset matching
+/// `pattern`, where `*` and `?` are wildcards.
+///
+/// `api` is the base URL of the repository in the forge's API; GitHub and Forgejo share the same
+/// layout for releases.
+async fn resolve_release(
+    api: &str,
+    tag: &str,
+    pattern: &str,
+    headers: &HeaderMap,
+) -> Result<(String, ReleaseAsset)> {
+    #[derive(Deserialize)]
+    struct Release {
+        tag_name: String,
+        assets: Vec<ReleaseAsset>,
+    }
+
+    let mut url: Url = format!("{api}/releases").parse()?;
+
+    {
+        let mut segments = url
+            .path_segments_mut()
+            .map_err(|()| eyre!("could not construct release API URL"))?;
+
+        if tag == LATEST_RELEASE {
+            segments.push(LATEST_RELEASE);
+        } else {
+            segments.extend(["tags", tag]);
+        }
+    }
+
+    let release: Release = utils::download::client()
+        .get(url)
+        .headers(headers.clone())
+        .send_with_retry()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let regex = Regex::new(&format!(
+        "^{}$",
+        regex::escape(pattern)
+            .replace(r"\*", ".*")
+            .replace(r"\?", ".")
+    ))?;
+
+    let mut matching = release
+        .assets
+        .iter()
+        .enumerate()
+        .filter(|(_, asset)| regex.is_match(&asset.name))
+        .map(|(i, _)| i);
+
+    match (matching.next(), matching.next()) {
+        (Some(i), None) => {
+            let mut assets = release.assets;
+            Ok((release.tag_name, assets.swap_remove(i)))
+        }
+        (Some(_), Some(_)) => bail!(
+            "multiple assets of release {} match {pattern:?}",
+            release.tag_name
+        ),
+        (None, _) => bail!(
+            "no asset of release {} matches {pattern:?} (available: {})",
+            release.tag_name,
+            release
+                .assets
+                .iter()
+                .map(|asset| asset.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Whether a path refers to a single stylesheet rather than an archive.
+fn is_stylesheet(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("css"))
+}
+
+/// Whether a path can be used as a local source, i.e. it is a directory, an archive or a stylesheet.
+fn is_local_source(path: &Path) -> bool {
+    path.is_dir()
+        || (path.is_file()
+            && (is_stylesheet(path) || utils::archive::Format::from_file(path).is_ok()))
+}
+
+/// Options that control how a source is retrieved.
+#[derive(Clone, Debug, Default)]
+pub struct RetrieveOptions {
+    /// Hashes that downloaded archives are verified against
+    pub integrity: Vec<Integrity>,
+    /// Only use previously cached artifacts instead of accessing the network
+    pub offline: bool,
+    /// Don't report the progress of downloads
+    pub quiet: bool,
+    /// Limits enforced when extracting archives
+    pub limits: ExtractionLimits,
+    /// Access tokens for forges, keyed by host
+    pub tokens: BTreeMap<String, String>,
+    /// Additional headers sent with every request
+    pub headers: HeaderMap,
+}
 
 #[derive(Clone, Debug)]
 pub enum ParsedSource {
-    GitHub { repo: String, r#ref: String },
-    Codeberg { repo: String, r#ref: String },
-    GitLab { repo: String, r#ref: String },
-    Url { inner: Url, implicit: bool },
-    Path { inner: PathBuf, implicit: bool },
+    GitHub {
+        repo: String,
+        r#ref: GitRef,
+        subdir: Option<String>,
+    },
+    Codeberg {
+        repo: String,
+        r#ref: GitRef,
+        subdir: Option<String>,
+    },
+    Forgejo {
+        host: String,
+        repo: String,
+        r#ref: GitRef,
+        subdir: Option<String>,
+    },
+    GitLab {
+        host: String,
+        repo: String,
+        r#ref: GitRef,
+        subdir: Option<String>,
+    },
+    SourceHut {
+        repo: String,
+        r#ref: GitRef,
+        subdir: Option<String>,
+    },
+    Bitbucket {
+        repo: String,
+        r#ref: GitRef,
+        subdir: Option<String>,
+    },
+    Git {
+        url: Url,
+        r#ref: Option<GitRef>,
+        subdir: Option<String>,
+    },
+    GitHubRelease {
+        repo: String,
+        tag: String,
+        asset: String,
+    },
+    ForgejoRelease {
+        host: String,
+        repo: String,
+        tag: String,
+        asset: String,
+    },
+    Url {
+        inner: Url,
+        implicit: bool,
+    },
+    Path {
+        inner: PathBuf,
+        implicit: bool,
+    },
 }
 
 impl ParsedSource {
@@ -40,48 +543,541 @@ impl ParsedSource {
         matches!(self, Self::Url { .. } | Self::Path { .. })
     }
 
-    pub async fn retrieve(&self, target: impl AsRef<Path>) -> Result<()> {
+    /// Returns the host of a forge source, along with the environment variables that an access token
+    /// for it can be read from.
+    ///
+    /// Tokens from the environment are only sent to the canonical host of a forge, so that a source
+    /// naming an arbitrary self-hosted instance cannot collect them.
+    fn forge(&self) -> Option<(&str, &'static [&'static str])> {
+        let host = match self {
+            Self::GitHub { .. } | Self::GitHubRelease { .. } => "github.com",
+            Self::Codeberg { .. } => "codeberg.org",
+            Self::Forgejo { host, .. }
+            | Self::ForgejoRelease { host, .. }
+            | Self::GitLab { host, .. } => host,
+            _ => return None,
+        };
+
+        let vars: &'static [&'static str] = match (self, host) {
+            (Self::GitHub { .. } | Self::GitHubRelease { .. }, _) => &["GITHUB_TOKEN", "GH_TOKEN"],
+            (Self::GitLab { .. }, GITLAB_DEFAULT_HOST) => &["GITLAB_TOKEN"],
+            (
+                Self::Codeberg { .. } | Self::Forgejo { .. } | Self::ForgejoRelease { .. },
+                "codeberg.org",
+            ) => &["CODEBERG_TOKEN"],
+            _ => &[],
+        };
+
+        Some((host, vars))
+    }
+
+    /// Returns the access token for a forge source, preferring the config over the environment.
+    fn token(&self, options: &RetrieveOptions) -> Option<String> {
+        let (host, vars) = self.forge()?;
+
+        options.tokens.get(host).cloned().or_else(|| {
+            vars.iter()
+                .find_map(|var| std::env::var(var).ok().filter(|token| !token.is_empty()))
+        })
+    }
+
+    /// Builds the headers sent with every request for this source, adding credentials from
+    /// access tokens or the netrc file unless they are already provided in `options.headers`.
+    async fn headers(&self, options: &RetrieveOptions) -> Result<HeaderMap> {
+        let mut headers = options.headers.clone();
+
+        let credentials = if let Some(token) = self.token(options) {
+            match self {
+                Self::GitLab { .. } => Some((HeaderName::from_static("private-token"), token)),
+                Self::Codeberg { .. } | Self::Forgejo { .. } | Self::ForgejoRelease { .. } => {
+                    Some((header::AUTHORIZATION, format!("token {token}")))
+                }
+                _ => Some((header::AUTHORIZATION, format!("Bearer {token}"))),
+            }
+        } else if let Self::Url { inner: url, .. } = self
+            && url.username().is_empty()
+            && let Some(host) = url.host_str()
+            && let Some(netrc) = utils::netrc::lookup(host).await?
+        {
+            let basic = BASE64.encode(format!("{}:{}", netrc.login, netrc.password));
+            Some((header::AUTHORIZATION, format!("Basic {basic}")))
+        } else {
+            None
+        };
+
+        if let Some((name, value)) = credentials {
+            let mut value = HeaderValue::from_str(&value)?;
+            value.set_sensitive(true);
+
+            headers.entry(name).or_insert(value);
+        }
+
+        Ok(headers)
+    }
+
+    /// Resolves the source to an immutable revision where possible, so that it can be locked.
+    pub async fn resolve(&self, options: &RetrieveOptions) -> Result<Self> {
+        let headers = self.headers(options).await?;
+
+        let sha = match self {
+            Self::GitHub { repo, r#ref, .. } => Some(resolve_github(repo, r#ref, &headers).await?),
+            Self::Codeberg { repo, r#ref, .. } => {
+                Some(resolve_forgejo("codeberg.org", repo, r#ref, &headers).await?)
+            }
+            Self::Forgejo {
+                host, repo, r#ref, ..
+            } => Some(resolve_forgejo(host, repo, r#ref, &headers).await?),
+            Self::GitLab {
+                host, repo, r#ref, ..
+            } => Some(resolve_gitlab(host, repo, r#ref, &headers).await?),
+            // SourceHut does not provide an unauthenticated API, so ask Git instead
+            Self::SourceHut { repo, r#ref, .. } => {
+                let url: Url = format!("https://git.sr.ht/{repo}").parse()?;
+                let sha = utils::git::resolve(&url, Some(r#ref)).await?;
+
+                // the archive endpoint can't tell branches and tags apart, so they must be resolved here
+                if sha.is_none() && matches!(r#ref, GitRef::Branch(_) | GitRef::Tag(_)) {
+                    bail!("could not resolve {ref} in git.sr.ht/{repo}");
+                }
+
+                sha
+            }
+            Self::Bitbucket { repo, r#ref, .. } => {
+                Some(resolve_bitbucket(repo, r#ref, &headers).await?)
+            }
+            Self::Git { url, r#ref, .. } => utils::git::resolve(url, r#ref.as_ref()).await?,
+            Self::GitHubRelease { repo, tag, asset } => {
+                let api = format!("https://api.github.com/repos/{repo}");
+                let (tag, asset) = resolve_release(&api, tag, asset, &headers).await?;
+
+                return Ok(Self::GitHubRelease {
+                    repo: repo.clone(),
+                    tag,
+                    asset: asset.name,
+                });
+            }
+            Self::ForgejoRelease {
+                host,
+                repo,
+                tag,
+                asset,
+            } => {
+                let api = format!("https://{host}/api/v1/repos/{repo}");
+                let (tag, asset) = resolve_release(&api, tag, asset, &headers).await?;
+
+                return Ok(Self::ForgejoRelease {
+                    host: host.clone(),
+                    repo: repo.clone(),
+                    tag,
+                    asset: asset.name,
+                });
+            }
+            Self::Url { .. } | Self::Path { .. } => None,
+        };
+
+        let mut resolved = self.clone();
+
+        if let Some(sha) = sha {
+            match &mut resolved {
+                Self::GitHub { r#ref, .. }
+                | Self::Codeberg { r#ref, .. }
+                | Self::Forgejo { r#ref, .. }
+                | Self::GitLab { r#ref, .. }
+                | Self::SourceHut { r#ref, .. }
+                | Self::Bitbucket { r#ref, .. } => *r#ref = GitRef::Commit(sha),
+                Self::Git { r#ref, .. } => *r#ref = Some(GitRef::Commit(sha)),
+                Self::GitHubRelease { .. }
+                | Self::ForgejoRelease { .. }
+                | Self::Url { .. }
+                | Self::Path { .. } => {}
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Whether the source already refers to an immutable revision and needs no resolving.
+    pub fn is_pinned(&self) -> bool {
+        match self {
+            Self::GitHub { r#ref, .. }
+            | Self::Codeberg { r#ref, .. }
+            | Self::Forgejo { r#ref, .. }
+            | Self::GitLab { r#ref, .. }
+            | Self::SourceHut { r#ref, .. }
+            | Self::Bitbucket { r#ref, .. } => matches!(r#ref, GitRef::Commit(_)),
+            Self::Git { r#ref, .. } => matches!(r#ref, Some(GitRef::Commit(_))),
+            Self::GitHubRelease { tag, asset, .. } | Self::ForgejoRelease { tag, asset, .. } => {
+                tag != LATEST_RELEASE && !asset.contains(['*', '?'])
+            }
+            Self::Url { .. } | Self::Path { .. } => true,
+        }
+    }
+
+    /// Whether the source is a single stylesheet instead of an archive or a directory.
+    pub fn is_single_file(&self) -> bool {
+        match self {
+            Self::Url { inner, .. } => is_stylesheet(Path::new(inner.path())),
+            Self::Path { inner, .. } => is_stylesheet(inner),
+            Self::GitHubRelease { asset, .. } | Self::ForgejoRelease { asset, .. } => {
+                is_stylesheet(Path::new(asset))
+            }
+            _ => false,
+        }
+    }
+
+    pub fn subdir(&self) -> Option<&str> {
+        match self {
+            Self::GitHub { subdir, .. }
+            | Self::Codeberg { subdir, .. }
+            | Self::Forgejo { subdir, .. }
+            | Self::GitLab { subdir, .. }
+            | Self::SourceHut { subdir, .. }
+            | Self::Bitbucket { subdir, .. }
+            | Self::Git { subdir, .. } => subdir.as_deref(),
+            Self::GitHubRelease { .. }
+            | Self::ForgejoRelease { .. }
+            | Self::Url { .. }
+            | Self::Path { .. } => None,
+        }
+    }
+
+    /// Retrieves the source into `target`, returning the SHA-256 of the downloaded archive, if any.
+    ///
+    /// Single-file sources are installed as `userChrome.css` in `target`.
+    /// The downloaded archive is verified against every entry in `options.integrity`.
+    /// If the source specifies a subdirectory, only that subdirectory is copied into `target`.
+    pub async fn retrieve(
+        &self,
+        target: impl AsRef<Path>,
+        options: &RetrieveOptions,
+    ) -> Result<Option<String>> {
         let target = target.as_ref();
 
+        let options = &RetrieveOptions {
+            headers: self.headers(options).await?,
+            ..options.clone()
+        };
+
+        if self.is_single_file() {
+            let sha256 = self
+                .retrieve_file_inner(&target.join("userChrome.css"), options)
+                .await?;
+
+            return Ok(Some(sha256));
+        }
+
+        let Some(subdir) = self.subdir() else {
+            return self.retrieve_all(target, options).await;
+        };
+
+        let temp_dir = TempDir::new()?;
+        let sha256 = self.retrieve_all(temp_dir.path(), options).await?;
+
+        let src = temp_dir.path().join(subdir);
+        if !src.is_dir() {
+            bail!("{subdir:?} is not a directory in {self}");
+        }
+
+        utils::copy_dir_all(&src, target).await?;
+
+        Ok(sha256)
+    }
+
+    /// Retrieves a single-file source to `dest`, returning its SHA-256.
+    ///
+    /// The file is verified against every entry in `options.integrity`.
+    pub async fn retrieve_file(&self, dest: &Path, options: &RetrieveOptions) -> Result<String> {
+        let options = &RetrieveOptions {
+            headers: self.headers(options).await?,
+            ..options.clone()
+        };
+
+        self.retrieve_file_inner(dest, options).await
+    }
+
+    async fn retrieve_file_inner(&self, dest: &Path, options: &RetrieveOptions) -> Result<String> {
         match self {
-            ParsedSource::GitHub { repo, r#ref } => {
-                let url: Url =
-                    format!("https://github.com/{repo}/archive/refs/heads/{ref}.tar.gz").parse()?;
-                utils::download::archive(&url, target).await?;
+            Self::Url { inner: url, .. } if self.is_single_file() => {
+                utils::download::file(url, dest, options).await
+            }
+
+            Self::GitHubRelease { .. } | Self::ForgejoRelease { .. } if self.is_single_file() => {
+                let (url, options) = self.release_download(options).await?;
+                utils::download::file(&url, dest, &options).await
             }
 
-            ParsedSource::Codeberg { repo, r#ref } => {
-                let url: Url =
-                    format!("https://codeberg.org/{repo}/archive/{ref}.tar.gz").parse()?;
-                utils::download::archive(&url, target).await?;
+            Self::Path { inner: path, .. } if self.is_single_file() => {
+                let digests = utils::integrity::hash_file(path).await?;
+                utils::integrity::verify_all(&options.integrity, &digests)?;
+
+                tokio::fs::copy(path, dest).await?;
+
+                Ok(digests.sha256_hex())
             }
 
-            ParsedSource::GitLab { repo, r#ref } => {
-                let url: Url =
-                    format!("https://gitlab.com/{repo}/-/archive/{ref}/source-{ref}.tar.gz")
-                        .parse()?;
-                utils::download::archive(&url, target).await?;
+            _ => bail!("{self} is not a single-file source"),
+        }
+    }
+
+    /// Returns the download URL of the asset of a resolved release source.
+    fn release_url(&self) -> Result<Url> {
+        let (host, repo, tag, asset) = match self {
+            Self::GitHubRelease { repo, tag, asset } => ("github.com", repo, tag, asset),
+            Self::ForgejoRelease {
+                host,
+                repo,
+                tag,
+                asset,
+            } => (host.as_str(), repo, tag, asset),
+            _ => bail!("{self} is not a release source"),
+        };
+
+        if !self.is_pinned() {
+            bail!("{self} has to be resolved before it can be retrieved");
+        }
+
+        let mut url: Url = format!("https://{host}/{repo}/releases/download").parse()?;
+
+        url.path_segments_mut()
+            .map_err(|()| eyre!("could not construct release download URL"))?
+            .extend([tag, asset]);
+
+        Ok(url)
+    }
+
+    /// Returns the URL to download the asset of a resolved release source from, along with the
+    /// options to download it with.
+    ///
+    /// Web downloads of release assets don't accept access tokens, so authenticated downloads of
+    /// GitHub release assets go through the API instead, which serves the same file.
+    async fn release_download(&self, options: &RetrieveOptions) -> Result<(Url, RetrieveOptions)> {
+        let mut options = options.clone();
+
+        if let Self::GitHubRelease { repo, tag, asset } = self
+            && options.headers.contains_key(header::AUTHORIZATION)
+        {
+            if !self.is_pinned() {
+                bail!("{self} has to be resolved before it can be retrieved");
             }
 
-            ParsedSource::Url { inner: url, .. } => {
-                utils::download::archive(url, target).await?;
+            let api = format!("https://api.github.com/repos/{repo}");
+            let (_, resolved) = resolve_release(&api, tag, asset, &options.headers).await?;
+            let url = resolved
+                .url
+                .ok_or_else(|| eyre!("GitHub did not return an API URL for {asset:?}"))?
+                .parse()?;
+
+            options.headers.insert(
+                header::ACCEPT,
+                HeaderValue::from_static("application/octet-stream"),
+            );
+
+            return Ok((url, options));
+        }
+
+        Ok((self.release_url()?, options))
+    }
+
+    /// Returns the URL of the archive to download for the source, if it is retrieved as one.
+    ///
+    /// Forge archives are always downloaded through the API, since web archive downloads don't accept
+    /// access tokens and the two produce different archives (and thus different hashes) for the same
+    /// revision.
+    fn archive_url(&self) -> Result<Option<Url>> {
+        // only GitHub accepts fully qualified refs for archives, so the other forges need a revision
+        // that was already resolved by `resolve`
+        if let ParsedSource::Codeberg { r#ref, .. }
+        | ParsedSource::Forgejo { r#ref, .. }
+        | ParsedSource::GitLab { r#ref, .. }
+        | ParsedSource::SourceHut { r#ref, .. }
+        | ParsedSource::Bitbucket { r#ref, .. } = self
+            && let GitRef::Branch(_) | GitRef::Tag(_) = r#ref
+        {
+            bail!("{ref} must be resolved before it can be downloaded");
+        }
+
+        Ok(Some(match self {
+            ParsedSource::GitHub { repo, r#ref, .. } => {
+                let path = match r#ref {
+                    GitRef::Auto(name) | GitRef::Commit(name) => name.to_owned(),
+                    GitRef::Branch(name) => format!("refs/heads/{name}"),
+                    GitRef::Tag(name) => format!("refs/tags/{name}"),
+                };
+
+                format!("https://api.github.com/repos/{repo}/tarball/{path}").parse()?
             }
 
-            ParsedSource::Path { inner: path, .. } => {
-                utils::copy_dir_all(path, target).await?;
+            ParsedSource::Codeberg { repo, r#ref, .. } => format!(
+                "https://codeberg.org/api/v1/repos/{repo}/archive/{}.tar.gz",
+                r#ref.name()
+            )
+            .parse()?,
+
+            ParsedSource::Forgejo {
+                host, repo, r#ref, ..
+            } => format!(
+                "https://{host}/api/v1/repos/{repo}/archive/{}.tar.gz",
+                r#ref.name()
+            )
+            .parse()?,
+
+            ParsedSource::GitLab {
+                host, repo, r#ref, ..
+            } => {
+                let mut url: Url = format!("https://{host}/api/v4/projects").parse()?;
+
+                url.path_segments_mut()
+                    .map_err(|()| eyre!("could not construct GitLab API URL"))?
+                    .extend([repo, "repository", "archive.tar.gz"]);
+                url.query_pairs_mut().append_pair("sha", r#ref.name());
+
+                url
             }
+
+            ParsedSource::SourceHut { repo, r#ref, .. } => {
+                format!("https://git.sr.ht/{repo}/archive/{}.tar.gz", r#ref.name()).parse()?
+            }
+
+            ParsedSource::Bitbucket { repo, r#ref, .. } => {
+                format!("https://bitbucket.org/{repo}/get/{}.tar.gz", r#ref.name()).parse()?
+            }
+
+            ParsedSource::Url { inner: url, .. } => url.clone(),
+
+            ParsedSource::GitHubRelease { .. }
+            | ParsedSource::ForgejoRelease { .. }
+            | ParsedSource::Git { .. }
+            | ParsedSource::Path { .. } => return Ok(None),
+        }))
+    }
+
+    async fn retrieve_all(
+        &self,
+        target: &Path,
+        options: &RetrieveOptions,
+    ) -> Result<Option<String>> {
+        if let Some(url) = self.archive_url()? {
+            return Ok(Some(utils::download::archive(&url, target, options).await?));
         }
 
-        Ok(())
+        Ok(match self {
+            ParsedSource::GitHubRelease { .. } | ParsedSource::ForgejoRelease { .. } => {
+                let (url, options) = self.release_download(options).await?;
+                Some(utils::download::archive(&url, target, &options).await?)
+            }
+
+            ParsedSource::Git { url, r#ref, .. } => {
+                if !options.integrity.is_empty() {
+                    bail!("integrity verification is not supported for Git sources");
+                }
+
+                utils::git::checkout(url, r#ref.as_ref(), target, options).await?;
+                None
+            }
+
+            ParsedSource::Path { inner: path, .. } => {
+                if path.is_file() {
+                    Some(
+                        utils::archive::verify_and_extract_file(
+                            path,
+                            utils::archive::Format::from_path(path).ok(),
+                            target,
+                            &options.integrity,
+                            options.limits,
+                        )
+                        .await?,
+                    )
+                } else {
+                    if !options.integrity.is_empty() {
+                        bail!("integrity verification is not supported for directory sources");
+                    }
+
+                    utils::copy_dir_all(path, target).await?;
+                    None
+                }
+            }
+
+            _ => unreachable!("{self} is retrieved as an archive"),
+        })
     }
 }
 
 impl Display for ParsedSource {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&match self {
-            Self::GitHub { repo, r#ref } => format!("github:{repo}#{ref}"),
-            Self::Codeberg { repo, r#ref } => format!("codeberg:{repo}#{ref}"),
-            Self::GitLab { repo, r#ref } => format!("gitlab:{repo}#{ref}"),
+            Self::GitHub {
+                repo,
+                r#ref,
+                subdir,
+            } => format!(
+                "github:{repo}{}",
+                fmt_revision(Some(r#ref), subdir.as_deref())
+            ),
+            Self::Codeberg {
+                repo,
+                r#ref,
+                subdir,
+            } => format!(
+                "codeberg:{repo}{}",
+                fmt_revision(Some(r#ref), subdir.as_deref())
+            ),
+            Self::Forgejo {
+                host,
+                repo,
+                r#ref,
+                subdir,
+            } => format!(
+                "forgejo:{host}/{repo}{}",
+                fmt_revision(Some(r#ref), subdir.as_deref())
+            ),
+            Self::GitLab {
+                host,
+                repo,
+                r#ref,
+                subdir,
+            } => format!(
+                "gitlab:{}{repo}{}",
+                if host == GITLAB_DEFAULT_HOST {
+                    String::new()
+                } else {
+                    format!("{host}/")
+                },
+                fmt_revision(Some(r#ref), subdir.as_deref())
+            ),
+
+            Self::SourceHut {
+                repo,
+                r#ref,
+                subdir,
+            } => format!(
+                "sourcehut:{repo}{}",
+                fmt_revision(Some(r#ref), subdir.as_deref())
+            ),
+            Self::Bitbucket {
+                repo,
+                r#ref,
+                subdir,
+            } => format!(
+                "bitbucket:{repo}{}",
+                fmt_revision(Some(r#ref), subdir.as_deref())
+            ),
+
+            Self::Git { url, r#ref, subdir } => {
+                format!(
+                    "git:{url}{}",
+                    fmt_revision(r#ref.as_ref(), subdir.as_deref())
+                )
+            }
+
+            Self::GitHubRelease { repo, tag, asset } => {
+                format!("github-release:{repo}@{tag}/{asset}")
+            }
+            Self::ForgejoRelease {
+                host,
+                repo,
+                tag,
+                asset,
+            } => format!("forgejo-release:{host}/{repo}@{tag}/{asset}"),
 
             Self::Url { inner, implicit } => {
                 format!("{}{inner}", if *implicit { "" } else { "url:" })
@@ -98,31 +1094,116 @@ impl Display for ParsedSource {
     }
 }
 
+/// Parses a `github-release:` or `forgejo-release:` source specification.
+fn parse_release(s: &str) -> Option<ParsedSource> {
+    if let Some(release) = GITHUB_RELEASE_REGEX.captures(s) {
+        return Some(ParsedSource::GitHubRelease {
+            repo: release["repo"].to_owned(),
+            tag: release["tag"].to_owned(),
+            asset: release["asset"].to_owned(),
+        });
+    }
+
+    FORGEJO_RELEASE_REGEX
+        .captures(s)
+        .map(|release| ParsedSource::ForgejoRelease {
+            host: release["host"].to_owned(),
+            repo: release["repo"].to_owned(),
+            tag: release["tag"].to_owned(),
+            asset: release["asset"].to_owned(),
+        })
+}
+
 impl FromStr for ParsedSource {
     type Err = eyre::Report;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Some(github) = GITHUB_REGEX.captures(s) {
+            let (r#ref, subdir) = parse_revision(github.name("ref").map_or("", |m| m.as_str()))?;
+
             return Ok(Self::GitHub {
                 repo: github["repo"].to_owned(),
-                r#ref: github.name("ref").map_or("main", |m| m.as_str()).into(),
+                r#ref: r#ref.unwrap_or_default(),
+                subdir,
             });
         }
 
         if let Some(codeberg) = CODEBERG_REGEX.captures(s) {
+            let (r#ref, subdir) = parse_revision(codeberg.name("ref").map_or("", |m| m.as_str()))?;
+
             return Ok(Self::Codeberg {
                 repo: codeberg["repo"].to_owned(),
-                r#ref: codeberg.name("ref").map_or("main", |m| m.as_str()).into(),
+                r#ref: r#ref.unwrap_or_default(),
+                subdir,
+            });
+        }
+
+        if let Some(forgejo) = FORGEJO_REGEX.captures(s) {
+            let (r#ref, subdir) = parse_revision(forgejo.name("ref").map_or("", |m| m.as_str()))?;
+
+            return Ok(Self::Forgejo {
+                host: forgejo["host"].to_owned(),
+                repo: forgejo["repo"].to_owned(),
+                r#ref: r#ref.unwrap_or_default(),
+                subdir,
             });
         }
 
         if let Some(gitlab) = GITLAB_REGEX.captures(s) {
+            let (r#ref, subdir) = parse_revision(gitlab.name("ref").map_or("", |m| m.as_str()))?;
+
+            // the first path segment is treated as a host if it looks like a domain name
+            // and is followed by at least a namespace and a project name
+            let (host, repo) = match gitlab["repo"].split_once('/') {
+                Some((host, repo)) if HOST_REGEX.is_match(host) && repo.contains('/') => {
+                    (host, repo)
+                }
+                _ => (GITLAB_DEFAULT_HOST, &gitlab["repo"]),
+            };
+
             return Ok(Self::GitLab {
-                repo: gitlab["repo"].to_owned(),
-                r#ref: gitlab.name("ref").map_or("main", |m| m.as_str()).into(),
+                host: host.to_owned(),
+                repo: repo.to_owned(),
+                r#ref: r#ref.unwrap_or_default(),
+                subdir,
+            });
+        }
+
+        if let Some(sourcehut) = SOURCEHUT_REGEX.captures(s) {
+            let (r#ref, subdir) = parse_revision(sourcehut.name("ref").map_or("", |m| m.as_str()))?;
+
+            return Ok(Self::SourceHut {
+                repo: sourcehut["repo"].to_owned(),
+                r#ref: r#ref.unwrap_or_default(),
+                subdir,
+            });
+        }
+
+        if let Some(bitbucket) = BITBUCKET_REGEX.captures(s) {
+            let (r#ref, subdir) = parse_revision(bitbucket.name("ref").map_or("", |m| m.as_str()))?;
+
+            return Ok(Self::Bitbucket {
+                repo: bitbucket["repo"].to_owned(),
+                r#ref: r#ref.unwrap_or_default(),
+                subdir,
             });
         }
 
+        if let Some(git) = s.strip_prefix("git:") {
+            let (url, revision) = git.rsplit_once('#').unwrap_or((git, ""));
+            let (r#ref, subdir) = parse_revision(revision)?;
+
+            return Ok(Self::Git {
+                url: url.parse()?,
+                r#ref,
+                subdir,
+            });
+        }
+
+        if let Some(release) = parse_release(s) {
+            return Ok(release);
+        }
+
         if let Some(url) = s.strip_prefix("url:") {
             return Ok(Self::Url {
                 inner: url.parse()?,
@@ -132,14 +1213,14 @@ impl FromStr for ParsedSource {
 
         if let Some(path) = s.strip_prefix("path:") {
             let parsed_path = Path::new(path);
-            if parsed_path.is_dir() {
+            if is_local_source(parsed_path) {
                 return Ok(Self::Path {
                     inner: parsed_path.canonicalize()?,
                     implicit: false,
                 });
             }
 
-            bail!("path {parsed_path:?} is not a directory")
+            bail!("path {parsed_path:?} is not a directory, a supported archive or a stylesheet")
         }
 
         if s.starts_with("https://") || s.starts_with("http://") {
@@ -150,7 +1231,7 @@ impl FromStr for ParsedSource {
         }
 
         let parsed_path = Path::new(s);
-        if parsed_path.is_dir() {
+        if is_local_source(parsed_path) {
             return Ok(Self::Path {
                 inner: parsed_path.canonicalize()?,
                 implicit: true,
@@ -160,3 +1241,113 @@ impl FromStr for ParsedSource {
         bail!("invalid source: {s:?}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        net::TcpListener,
+    };
+
+    /// Serves a mock of the releases API of `o/r`, returning its base URL along with the heads of
+    /// the requests it receives.
+    async fn mock_api() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let latest = format!(
+            r#"{{"tag_name":"v2","assets":[{{"name":"theme-v2.zip","url":"{base}/repos/o/r/releases/assets/2"}},{{"name":"checksums.txt","url":"{base}/repos/o/r/releases/assets/3"}}]}}"#
+        );
+        let tagged = r#"{"tag_name":"v1","assets":[{"name":"a.zip"},{"name":"b.zip"}]}"#;
+
+        let log = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0];
+                    if stream.read(&mut byte).await.unwrap() == 0 {
+                        break;
+                    }
+                    head.push(byte[0]);
+                }
+
+                let head = String::from_utf8(head).unwrap();
+                let path = head.split(' ').nth(1).unwrap_or_default().to_owned();
+                log.lock().unwrap().push(head);
+
+                let (status, body) = match path.as_str() {
+                    "/repos/o/r/releases/latest" => ("200 OK", latest.as_str()),
+                    "/repos/o/r/releases/tags/v1" => ("200 OK", tagged),
+                    _ => ("404 Not Found", r#"{"message":"Not Found"}"#),
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (base, requests)
+    }
+
+    #[tokio::test]
+    async fn releases() {
+        let (base, requests) = mock_api().await;
+        let api = format!("{base}/repos/o/r");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer t"));
+
+        let (tag, asset) = resolve_release(&api, LATEST_RELEASE, "theme-*.zip", &headers)
+            .await
+            .unwrap();
+        assert_eq!(tag, "v2");
+        assert_eq!(asset.name, "theme-v2.zip");
+        assert_eq!(
+            asset.url,
+            Some(format!("{base}/repos/o/r/releases/assets/2"))
+        );
+        assert!(
+            requests.lock().unwrap()[0]
+                .to_ascii_lowercase()
+                .contains("authorization: bearer t")
+        );
+
+        let (tag, asset) = resolve_release(&api, "v1", "b.zip", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!((tag.as_str(), asset.name.as_str()), ("v1", "b.zip"));
+        assert_eq!(asset.url, None);
+
+        let ambiguous = resolve_release(&api, "v1", "*.zip", &HeaderMap::new()).await;
+        assert!(
+            ambiguous
+                .unwrap_err()
+                .to_string()
+                .contains("multiple assets")
+        );
+
+        let missing = resolve_release(&api, "v1", "c.zip", &HeaderMap::new()).await;
+        assert!(
+            missing
+                .unwrap_err()
+                .to_string()
+                .contains("available: a.zip, b.zip")
+        );
+
+        assert!(
+            resolve_release(&api, "v3", "*.zip", &HeaderMap::new())
+                .await
+                .is_err()
+        );
+    }
+}
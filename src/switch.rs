@@ -5,16 +5,25 @@
 use eyre::{Result, bail, eyre};
 use temp_dir::TempDir;
 
-use std::{io, path::Path, process::Stdio};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+};
 use tokio::{fs, process::Command};
 
-use anstream::println;
+use anstream::{eprintln, println};
 use owo_colors::OwoColorize as _;
 
 use crate::{
+    backup,
     config::{PrintContext, Userchrome, UserchromeConfig},
-    source::ParsedSource,
-    utils,
+    lock::{LockedUserchrome, Lockfile},
+    prefs::{self, Pref, PrefKind, PrefValue},
+    source::{ParsedSource, RetrieveOptions},
+    utils::{self, integrity::Integrity, transaction::Transaction},
 };
 
 #[cfg(windows)]
@@ -39,10 +48,79 @@ async fn run_arkenfox_script(profile: &Path, name: &str, args: &[&str]) -> Resul
     Ok(())
 }
 
-const START_LINE: &str = "/** nyoom-managed config; do not edit */";
-const END_LINE: &str = "/** end of nyoom-managed config */";
+const STYLESHEETS_PREF: &str = "toolkit.legacyUserProfileCustomizations.stylesheets";
+
+/// Warns about prefs that are set by nyoom but also elsewhere in a user file,
+/// or that are set multiple times with different values.
+fn warn_conflicts(f: &Path, contents: &str, managed: &RangeInclusive<usize>) {
+    let file_name = f.file_name().unwrap_or_default().to_string_lossy();
+
+    let prefs = match prefs::parse(contents) {
+        Ok(prefs) => prefs,
+        Err(err) => {
+            eprintln!(
+                "{} could not check {file_name} for conflicting prefs: {err}",
+                "warning:".yellow()
+            );
+            return;
+        }
+    };
+
+    let mut assignments: BTreeMap<&str, Vec<&Pref>> = BTreeMap::new();
+    for pref in &prefs {
+        assignments.entry(&pref.key).or_default().push(pref);
+    }
+
+    for (key, assignments) in assignments.iter().filter(|(_, a)| a.len() > 1) {
+        let Some(effective) = prefs::effective(assignments) else {
+            continue;
+        };
+
+        let differs = assignments.iter().any(|a| a.value != effective.value);
+
+        if assignments.iter().any(|a| managed.contains(&a.line)) {
+            eprintln!(
+                "{} {} is set by nyoom but also on {} of {file_name}{}",
+                "warning:".yellow(),
+                key.magenta(),
+                join_lines(assignments.iter().filter(|a| !managed.contains(&a.line))),
+                if differs && !managed.contains(&effective.line) {
+                    format!(", which overrides it with {}", effective.value)
+                } else {
+                    String::new()
+                }
+            );
+        } else if differs {
+            eprintln!(
+                "{} {} is set to different values on {} of {file_name}; only line {} takes effect",
+                "warning:".yellow(),
+                key.magenta(),
+                join_lines(assignments.iter()),
+                effective.line
+            );
+        }
+    }
+}
+
+/// Formats the lines of pref assignments, e.g. `line 3` or `lines 3, 5`.
+fn join_lines<'a>(assignments: impl Iterator<Item = &'a &'a Pref>) -> String {
+    let lines = assignments.map(|a| a.line.to_string()).collect::<Vec<_>>();
 
-async fn patch_user_file(f: &Path, userchrome: Option<&Userchrome>, now: &str) -> Result<()> {
+    format!(
+        "{} {}",
+        if lines.len() == 1 { "line" } else { "lines" },
+        lines.join(", ")
+    )
+}
+
+/// Patches the nyoom-managed block of a user file with the configs of `userchrome`,
+/// returning the keys of prefs that were removed from the block.
+async fn patch_user_file(
+    f: &Path,
+    userchrome: Option<&Userchrome>,
+    now: &str,
+    tx: &mut Transaction,
+) -> Result<Vec<String>> {
     let contents = match fs::read_to_string(f).await {
         Ok(contents) => contents,
         Err(err) => {
@@ -56,57 +134,124 @@ async fn patch_user_file(f: &Path, userchrome: Option<&Userchrome>, now: &str) -
 
     let lines = contents.lines().collect::<Vec<_>>();
 
-    let mut new_lines = vec![
-        "user_pref(\"toolkit.legacyUserProfileCustomizations.stylesheets\", true);".to_owned(),
-    ];
+    let mut new_keys = vec![STYLESHEETS_PREF];
+    let mut new_lines = vec![format!(
+        "user_pref({}, true);",
+        PrefValue::String(STYLESHEETS_PREF.to_owned())
+    )];
 
     if let Some(userchrome) = userchrome {
-        for c in &userchrome.configs {
-            let UserchromeConfig { key, value, raw } = c;
+        for UserchromeConfig { key, value } in &userchrome.configs {
+            new_keys.push(key);
 
-            let value = if *raw {
-                value.clone()
-            } else {
-                format!("\"{value}\"")
-            };
-
-            new_lines.push(format!("user_pref(\"{key}\", {value});"));
+            let key = PrefValue::String(key.clone());
+            new_lines.push(format!("user_pref({key}, {value});"));
         }
     }
 
+    let mut removed = Vec::new();
+
     let mut ret_lines: Vec<&str> = Vec::new();
-    let start_idx = lines.iter().position(|k| k == &START_LINE);
-    let end_idx = lines.iter().position(|k| k == &END_LINE);
+    let block = prefs::managed_block(&lines);
+
+    if let Some((start_idx, end_idx)) = block {
+        // a block that can't be parsed was edited by hand, so its prefs are left alone
+        if let Ok(old) = prefs::parse(&lines[start_idx + 1..end_idx].join("\n")) {
+            removed.extend(
+                old.into_iter()
+                    .map(|pref| pref.key)
+                    .filter(|key| !new_keys.contains(&key.as_str())),
+            );
+        }
 
-    if let (Some(start_idx), Some(end_idx)) = (start_idx, end_idx) {
         ret_lines.extend(lines[0..=start_idx].iter());
         ret_lines.extend(new_lines.iter().map(|s| s.as_str()));
         ret_lines.extend(lines[end_idx..].iter());
     } else {
-        fs::rename(
-            &f,
-            f.with_file_name(format!(
-                "{}.nyoom-{now}.bak",
-                f.file_name()
-                    .ok_or_else(|| eyre!("could not obtain file name"))?
-                    .to_string_lossy()
-            )),
-        )
-        .await?;
-
         ret_lines.clone_from(&lines);
-        ret_lines.push(START_LINE);
+        ret_lines.push(prefs::START_LINE);
         ret_lines.extend(new_lines.iter().map(|s| s.as_str()));
-        ret_lines.push(END_LINE);
+        ret_lines.push(prefs::END_LINE);
     }
 
     if !ret_lines.last().is_some_and(|s| s.is_empty()) {
         ret_lines.push("");
     }
 
-    fs::write(&f, ret_lines.join("\n")).await?;
+    let contents = ret_lines.join("\n");
 
-    Ok(())
+    let file_name = f
+        .file_name()
+        .ok_or_else(|| eyre!("could not obtain file name"))?
+        .to_string_lossy();
+
+    let staged = f.with_file_name(format!("{file_name}.nyoom-staging"));
+    tx.create(&staged);
+    fs::write(&staged, &contents).await?;
+
+    if block.is_none() && f.exists() {
+        tx.rename(f, &f.with_file_name(format!("{file_name}.nyoom-{now}.bak")))
+            .await?;
+    } else {
+        tx.modify(f).await?;
+    }
+
+    tx.rename(&staged, f).await?;
+
+    let managed = prefs::managed_block(&ret_lines)
+        .map_or(0..=0, |(start_idx, end_idx)| start_idx + 1..=end_idx + 1);
+
+    warn_conflicts(f, &contents, &managed);
+
+    Ok(removed)
+}
+
+/// Clears prefs from Firefox's `prefs.js`, so that prefs removed from the user file don't stay
+/// in effect. Returns the number of prefs that were cleared.
+async fn reset_prefs(profile: &Path, keys: &[String], tx: &mut Transaction) -> Result<usize> {
+    let path = profile.join("prefs.js");
+    if keys.is_empty() || !path.exists() {
+        return Ok(0);
+    }
+
+    let contents = fs::read_to_string(&path).await?;
+
+    // clearing stale prefs is best-effort, so an unparsable prefs.js shouldn't abort switching
+    let prefs = match prefs::parse(&contents) {
+        Ok(prefs) => prefs,
+        Err(err) => {
+            eprintln!(
+                "{} could not clear removed prefs from prefs.js: {err}",
+                "warning:".yellow()
+            );
+            return Ok(0);
+        }
+    };
+
+    // Firefox writes every pref on its own line
+    let stale = prefs
+        .into_iter()
+        .filter(|pref| pref.kind == PrefKind::User && keys.contains(&pref.key))
+        .map(|pref| pref.line)
+        .collect::<BTreeSet<_>>();
+
+    if stale.is_empty() {
+        return Ok(0);
+    }
+
+    let mut new_contents = contents
+        .lines()
+        .enumerate()
+        .filter(|(idx, _)| !stale.contains(&(idx + 1)))
+        .map(|(_, line)| line)
+        .collect::<Vec<_>>()
+        .join("\n");
+    new_contents.push('\n');
+
+    tx.modify(&path).await?;
+    fs::write(&path, new_contents).await?;
+
+    Ok(stale.len())
 }
 
 async fn apply_user_file(
@@ -114,82 +259,371 @@ async fn apply_user_file(
     profile: &Path,
     now: &str,
     step_counter: &mut i32,
+    tx: &mut Transaction,
 ) -> Result<()> {
     let arkenfox = profile.join("user-overrides.js").exists();
 
-    if arkenfox {
-        patch_user_file(&profile.join("user-overrides.js"), userchrome, now).await?;
+    let removed = if arkenfox {
+        let removed =
+            patch_user_file(&profile.join("user-overrides.js"), userchrome, now, tx).await?;
 
         println!("{} updating arkenfox", step_counter.green());
         *step_counter += 1;
 
+        tx.modify(&profile.join("user.js")).await?;
+        tx.modify(&profile.join("prefs.js")).await?;
+
         run_arkenfox_script(profile, "updater", &["-s"]).await?;
         run_arkenfox_script(profile, "prefsCleaner", &["-s"]).await?;
+
+        removed
     } else {
-        patch_user_file(&profile.join("user.js"), userchrome, now).await?;
+        patch_user_file(&profile.join("user.js"), userchrome, now, tx).await?
+    };
+
+    let reset = reset_prefs(profile, &removed, tx).await?;
+    if reset > 0 {
+        println!(
+            "{} {}",
+            "╰".cyan().dimmed(),
+            format!(
+                "reset {reset} {} that {} no longer set",
+                if reset == 1 { "pref" } else { "prefs" },
+                if reset == 1 { "is" } else { "are" }
+            )
+            .dimmed()
+        );
     }
 
     Ok(())
 }
 
-pub async fn switch(userchrome: Option<&Userchrome>, profile: &Path) -> Result<()> {
-    if let Some(userchrome) = userchrome {
-        userchrome.print(false, PrintContext::Normal);
-        println!();
+const CHROME_FILES: [&str; 2] = ["userChrome.css", "userContent.css"];
+
+/// Finds the directory to install as the `chrome` directory within a retrieved source.
+///
+/// This is the topmost directory containing a `userChrome.css` or `userContent.css`, falling back to
+/// a `chrome` directory at the root or the root itself. Deeper directories are ignored, and a `chrome`
+/// directory at the root wins over its siblings; any other candidates at the same depth are ambiguous.
+/// Hidden directories are not searched.
+async fn find_chrome_dir(root: &Path) -> Result<PathBuf> {
+    let mut candidates = Vec::new();
+    let mut level = vec![root.to_owned()];
+
+    while candidates.is_empty() && !level.is_empty() {
+        let mut next = Vec::new();
+
+        for dir in level {
+            if CHROME_FILES.iter().any(|f| dir.join(f).is_file()) {
+                candidates.push(dir);
+                continue;
+            }
+
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_type().await?.is_dir()
+                    && !entry.file_name().to_string_lossy().starts_with('.')
+                {
+                    next.push(entry.path());
+                }
+            }
+        }
+
+        level = next;
     }
 
-    let mut step_counter = 1;
-    let now = chrono::Local::now().format("%Y-%m-%d-%H-%M-%S").to_string();
+    candidates.sort();
 
-    if let Some(userchrome) = userchrome {
-        println!("{} retrieving source", step_counter.green());
-        step_counter += 1;
+    // a `chrome` directory at the root is what Firefox expects, so it wins over its siblings
+    let chrome = root.join("chrome");
+    if candidates.contains(&chrome) {
+        return Ok(chrome);
+    }
 
-        let temp_dir = TempDir::new()?;
+    match candidates.len() {
+        0 => Ok(if root.join("chrome").is_dir() {
+            root.join("chrome")
+        } else {
+            root.to_owned()
+        }),
+        1 => Ok(candidates.remove(0)),
+        _ => bail!(
+            "found multiple directories containing userchrome files ({}); specify one with `nyoom add --chrome-dir` or by setting `chrome_dir` for the userchrome in your config",
+            candidates
+                .iter()
+                .map(|dir| dir.strip_prefix(root).unwrap_or(dir).display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Resolves the directory to install as the `chrome` directory within a retrieved source,
+/// honoring the userchrome's `chrome_dir` if it is set.
+async fn chrome_dir(userchrome: &Userchrome, root: &Path) -> Result<PathBuf> {
+    let Some(chrome_dir) = &userchrome.chrome_dir else {
+        return find_chrome_dir(root).await;
+    };
+
+    if Path::new(chrome_dir)
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        bail!("invalid chrome directory: {chrome_dir:?}");
+    }
+
+    let dir = root.join(chrome_dir);
+    if !dir.is_dir() {
+        bail!("{chrome_dir:?} is not a directory in {}", userchrome.source);
+    }
+
+    Ok(dir)
+}
 
-        userchrome
-            .source
-            .parse::<ParsedSource>()?
-            .retrieve(&temp_dir)
+/// Retrieves a userchrome into `target`, installing the `locked` revision unless `update_lock` is set,
+/// and returns the entry to record in the lockfile.
+pub async fn retrieve_locked(
+    userchrome: &Userchrome,
+    target: &Path,
+    locked: Option<&LockedUserchrome>,
+    update_lock: bool,
+    options: &RetrieveOptions,
+) -> Result<LockedUserchrome> {
+    if options.offline && update_lock {
+        bail!("the lockfile cannot be updated in offline mode");
+    }
+
+    let locked = locked.filter(|_| !update_lock);
+
+    // the userContent.css source is retrieved without the integrity and headers of the main source,
+    // since it may be hosted elsewhere
+    let base_options = options;
+    let mut options = options.clone();
+    options.integrity.extend(userchrome.integrity()?);
+    options.headers.extend(userchrome.headers()?);
+
+    let resolved = if let Some(locked) = locked {
+        if let Some(sha256) = &locked.sha256 {
+            options.integrity.push(Integrity::sha256_hex(sha256)?);
+        }
+
+        locked.resolved.parse::<ParsedSource>()?
+    } else {
+        let source = userchrome.source.parse::<ParsedSource>()?;
+
+        if options.offline {
+            if !source.is_pinned() {
+                bail!(
+                    "{:?} has not been locked yet and cannot be resolved in offline mode",
+                    userchrome.name
+                );
+            }
+
+            source
+        } else {
+            source.resolve(&options).await?
+        }
+    };
+
+    let resolved_str = resolved.to_string();
+    if resolved_str != userchrome.source && !options.quiet {
+        println!("{} {}", "╰".cyan().dimmed(), resolved_str.dimmed());
+    }
+
+    let sha256 = resolved.retrieve(target, &options).await?;
+
+    let (user_content_resolved, user_content_sha256) = match &userchrome.user_content {
+        Some(user_content) => {
+            if !resolved.is_single_file() {
+                bail!(
+                    "{:?} can only have a userContent.css source if its own source is a single stylesheet",
+                    userchrome.name
+                );
+            }
+
+            let (resolved, sha256) = retrieve_user_content(
+                user_content,
+                &target.join("userContent.css"),
+                locked,
+                base_options,
+            )
             .await?;
 
+            (Some(resolved), Some(sha256))
+        }
+        None => (None, None),
+    };
+
+    Ok(LockedUserchrome {
+        name: userchrome.name.clone(),
+        source: userchrome.source.clone(),
+        resolved: resolved_str,
+        sha256,
+        user_content: userchrome.user_content.clone(),
+        user_content_resolved,
+        user_content_sha256,
+    })
+}
+
+/// Retrieves the userContent.css source of a userchrome to `dest`, installing exactly the locked
+/// file if it has been locked. Returns the resolved source and the SHA-256 of the file.
+async fn retrieve_user_content(
+    user_content: &str,
+    dest: &Path,
+    locked: Option<&LockedUserchrome>,
+    options: &RetrieveOptions,
+) -> Result<(String, String)> {
+    let mut options = options.clone();
+
+    let locked = locked.and_then(|locked| {
+        locked
+            .user_content_resolved
+            .as_ref()
+            .zip(locked.user_content_sha256.as_ref())
+    });
+
+    let resolved = if let Some((resolved, sha256)) = locked {
+        options.integrity.push(Integrity::sha256_hex(sha256)?);
+        resolved.parse::<ParsedSource>()?
+    } else {
+        let source = user_content.parse::<ParsedSource>()?;
+
+        if options.offline {
+            if !source.is_pinned() {
+                bail!(
+                    "{user_content:?} has not been locked yet and cannot be resolved in offline mode"
+                );
+            }
+
+            source
+        } else {
+            source.resolve(&options).await?
+        }
+    };
+
+    let sha256 = resolved.retrieve_file(dest, &options).await?;
+
+    Ok((resolved.to_string(), sha256))
+}
+
+/// Installs a retrieved userchrome (or removes the current one) and applies its configs,
+/// recording every change to the profile in `tx`.
+async fn install(
+    userchrome: Option<(&Userchrome, &Path)>,
+    profile: &Path,
+    now: &str,
+    step_counter: &mut i32,
+    tx: &mut Transaction,
+) -> Result<()> {
+    let chrome_dir = profile.join("chrome");
+
+    if let Some((userchrome, src_chrome_dir)) = userchrome {
         println!("{} installing userchrome", step_counter.green());
         println!("{} {}", "╰".cyan().dimmed(), profile.display().dimmed());
-        step_counter += 1;
+        *step_counter += 1;
 
-        let new_chrome_dir = profile.join("chrome");
-        let new_chrome_name_file = new_chrome_dir.join(".nyoom-chrome-name");
+        let staged = profile.join("chrome.nyoom-staging");
+        if staged.exists() {
+            fs::remove_dir_all(&staged).await?;
+        }
 
-        if new_chrome_dir.exists() {
-            if new_chrome_name_file.exists() {
-                fs::remove_dir_all(&new_chrome_dir).await?;
+        tx.create(&staged);
+        utils::copy_dir_all(src_chrome_dir, &staged).await?;
+        fs::write(staged.join(".nyoom-chrome-name"), &userchrome.name).await?;
+
+        if chrome_dir.exists() {
+            if chrome_dir.join(".nyoom-chrome-name").exists() {
+                tx.remove(&chrome_dir).await?;
             } else {
-                fs::rename(
-                    &new_chrome_dir,
+                tx.rename(
+                    &chrome_dir,
                     &profile.join(format!("chrome.nyoom-{now}.bak")),
                 )
                 .await?;
             }
         }
 
-        let src_chrome_dir = if temp_dir.path().join("chrome").exists() {
-            &temp_dir.path().join("chrome")
-        } else {
-            temp_dir.path()
-        };
-
-        utils::copy_dir_all(src_chrome_dir, &new_chrome_dir).await?;
-        fs::write(&new_chrome_name_file, &userchrome.name).await?;
+        tx.rename(&staged, &chrome_dir).await?;
     } else {
         println!("{} removing userchrome", step_counter.green());
-        step_counter += 1;
-        fs::remove_dir_all(profile.join("chrome")).await?;
+        *step_counter += 1;
+
+        if chrome_dir.exists() {
+            tx.remove(&chrome_dir).await?;
+        }
     }
 
     println!("{} applying user.js", step_counter.green());
-    step_counter += 1;
+    *step_counter += 1;
+
+    apply_user_file(userchrome.map(|(u, _)| u), profile, now, step_counter, tx).await
+}
+
+/// Switches the profile to `userchrome`, or removes the current userchrome if it is `None`.
+///
+/// Switching is all-or-nothing: if any step fails, all changes to the profile are rolled back.
+pub async fn switch(
+    userchrome: Option<&Userchrome>,
+    profile: &Path,
+    lock: &mut Lockfile,
+    update_lock: bool,
+    options: &RetrieveOptions,
+) -> Result<()> {
+    if let Some(userchrome) = userchrome {
+        userchrome.print(false, PrintContext::Normal);
+        println!();
+    }
+
+    let mut step_counter = 1;
+    let now = chrono::Local::now()
+        .format(backup::TIMESTAMP_FORMAT)
+        .to_string();
+
+    let temp_dir = TempDir::new()?;
+
+    let src_chrome_dir = if let Some(userchrome) = userchrome {
+        println!("{} retrieving source", step_counter.green());
+        step_counter += 1;
+
+        let locked = retrieve_locked(
+            userchrome,
+            temp_dir.path(),
+            lock.get(userchrome),
+            update_lock,
+            options,
+        )
+        .await?;
+
+        lock.insert(locked);
+
+        Some(chrome_dir(userchrome, temp_dir.path()).await?)
+    } else {
+        None
+    };
+
+    let mut tx = Transaction::default();
+
+    let result = install(
+        userchrome.zip(src_chrome_dir.as_deref()),
+        profile,
+        &now,
+        &mut step_counter,
+        &mut tx,
+    )
+    .await;
+
+    if let Err(err) = result {
+        if let Err(rollback_err) = tx.rollback().await {
+            return Err(err.wrap_err(format!(
+                "failed to roll back changes to the profile: {rollback_err}"
+            )));
+        }
+
+        println!("{}", "rolled back all changes to the profile".yellow());
+        return Err(err);
+    }
 
-    apply_user_file(userchrome, profile, &now, &mut step_counter).await?;
+    tx.commit().await?;
 
     println!("{}", "done!".green());
 
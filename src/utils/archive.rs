@@ -0,0 +1,727 @@
+// SPDX-FileCopyrightText: 2024 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    io::{self, BufRead, BufReader, Cursor, Read, Seek},
+    path::{Component, Path, PathBuf},
+};
+use tokio::{fs, sync::mpsc, task};
+
+use async_recursion::async_recursion;
+use bytes::Bytes;
+use eyre::{Result, bail, eyre};
+use temp_dir::TempDir;
+
+use bzip2::bufread::BzDecoder;
+use flate2::bufread::GzDecoder;
+use xz2::bufread::XzDecoder;
+use zip::ZipArchive;
+use zstd::stream::Decoder as ZstdDecoder;
+
+use super::integrity::{self, Integrity};
+use crate::config::ExtractionLimits;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+    TarBz2,
+    TarZst,
+}
+
+impl Format {
+    /// Detects the format of an archive from its leading magic bytes.
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::TarXz)
+        } else if data.starts_with(b"BZh") {
+            Some(Self::TarBz2)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::TarZst)
+        } else if is_tar_header(data) {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        let essence = mime.split(';').next().unwrap_or_default().trim();
+
+        match essence.to_ascii_lowercase().as_str() {
+            "application/zip" | "application/x-zip-compressed" => Some(Self::Zip),
+            "application/x-tar" => Some(Self::Tar),
+            "application/gzip" | "application/x-gzip" | "application/x-compressed-tar" => {
+                Some(Self::TarGz)
+            }
+            "application/x-xz" | "application/x-xz-compressed-tar" => Some(Self::TarXz),
+            "application/x-bzip2" | "application/x-bzip-compressed-tar" => Some(Self::TarBz2),
+            "application/zstd" | "application/x-zstd-compressed-tar" => Some(Self::TarZst),
+            _ => None,
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Result<Self> {
+        Ok(match ext {
+            "zip" => Self::Zip,
+            "tar" => Self::Tar,
+            "gz" | "tgz" => Self::TarGz,
+            "xz" | "txz" => Self::TarXz,
+            "bz2" | "tbz2" => Self::TarBz2,
+            "zst" | "tzst" => Self::TarZst,
+            _ => bail!("unsupported archive extension: {ext:?}"),
+        })
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let Some(ext) = path.extension().and_then(|s| s.to_str()) else {
+            bail!("could not infer file extension");
+        };
+
+        Self::from_extension(ext)
+    }
+
+    /// Detects the format of an archive file on disk from its leading magic bytes.
+    pub fn sniff_file(path: &Path) -> Result<Option<Self>> {
+        let mut header = Vec::with_capacity(TAR_BLOCK_SIZE);
+        std::fs::File::open(path)?
+            .take(TAR_BLOCK_SIZE as u64)
+            .read_to_end(&mut header)?;
+
+        Ok(Self::sniff(&header))
+    }
+
+    /// Detects the format of an archive file on disk, preferring its contents over its extension.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Self::sniff_file(path)?.map_or_else(|| Self::from_path(path), Ok)
+    }
+}
+
+pub const TAR_BLOCK_SIZE: usize = 512;
+
+/// Checks whether `data` starts with a valid tar header by verifying the header checksum,
+/// which works for all tar variants (including old V7 archives without the `ustar` magic).
+fn is_tar_header(data: &[u8]) -> bool {
+    let Some(header) = data.get(..TAR_BLOCK_SIZE) else {
+        return false;
+    };
+
+    let Some(expected) = std::str::from_utf8(&header[148..156])
+        .ok()
+        .map(|s| s.trim_matches(|c: char| c == '\0' || c == ' '))
+        .and_then(|s| u32::from_str_radix(s, 8).ok())
+    else {
+        return false;
+    };
+
+    let actual = header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if (148..156).contains(&i) {
+                u32::from(b' ')
+            } else {
+                u32::from(*b)
+            }
+        })
+        .sum::<u32>();
+
+    expected == actual
+}
+
+/// Wraps a decompressed stream, making sure that it actually contains a tarball.
+fn tar_stream<R: Read>(mut reader: R) -> Result<tar::Archive<impl Read>> {
+    let mut header = Vec::with_capacity(TAR_BLOCK_SIZE);
+    reader
+        .by_ref()
+        .take(TAR_BLOCK_SIZE as u64)
+        .read_to_end(&mut header)?;
+
+    if !is_tar_header(&header) {
+        bail!("compressed data is not a tar archive");
+    }
+
+    Ok(tar::Archive::new(Cursor::new(header).chain(reader)))
+}
+
+async fn strip_root(dir: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(dir).await?;
+
+    let first = entries.next_entry().await?;
+    let only_entry = entries.next_entry().await?.is_none();
+
+    if let Some(first) = first
+        && only_entry
+        && first.file_type().await?.is_dir()
+    {
+        let subdirectory = first.path();
+
+        let mut sub_entries = fs::read_dir(&subdirectory).await?;
+        while let Some(from) = sub_entries.next_entry().await? {
+            let to = dir.join(from.file_name());
+            fs::rename(from.path(), &to).await?;
+        }
+
+        fs::remove_dir(subdirectory).await?;
+    }
+
+    Ok(())
+}
+
+/// Tracks the resources used by an archive while it is being extracted.
+struct Budget {
+    limits: ExtractionLimits,
+    entries: u64,
+    size: u64,
+}
+
+impl Budget {
+    fn new(limits: ExtractionLimits) -> Self {
+        Self {
+            limits,
+            entries: 0,
+            size: 0,
+        }
+    }
+
+    /// Accounts for an entry of `size` bytes, bailing if the archive exceeds the configured limits.
+    fn add(&mut self, size: u64) -> Result<()> {
+        self.entries += 1;
+        self.size = self.size.saturating_add(size);
+
+        if self.entries > self.limits.max_entries {
+            bail!(
+                "archive contains more than {} entries",
+                self.limits.max_entries
+            );
+        }
+
+        if self.size > self.limits.max_size.as_u64() {
+            bail!(
+                "archive expands to more than {}",
+                self.limits.max_size.display().si()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Makes sure that an entry path stays inside the extraction root, rejecting absolute paths and `..` components.
+fn validate_entry_path(path: &Path) -> Result<PathBuf> {
+    let mut validated = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => validated.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                bail!(
+                    "archive entry {} escapes the extraction root",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    if validated.as_os_str().is_empty() {
+        bail!("archive contains an entry with an empty path");
+    }
+
+    Ok(validated)
+}
+
+/// Makes sure that a symlink at `path` pointing to `target` stays inside the extraction root.
+///
+/// `..` components are only accepted at the start of the target, since they could otherwise
+/// traverse other symlinks whose resolved location differs from their lexical one.
+fn validate_symlink(path: &Path, target: &Path) -> Result<()> {
+    let mut depth = path.components().count() - 1;
+    let mut descended = false;
+
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => {
+                depth += 1;
+                descended = true;
+            }
+            Component::CurDir => {}
+            Component::ParentDir if !descended && depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                bail!(
+                    "symlink {} -> {} points outside of the extraction root",
+                    path.display(),
+                    target.display()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates every entry of a tarball before unpacking it into `dest`.
+fn unpack_tar(
+    mut archive: tar::Archive<impl Read>,
+    dest: &Path,
+    limits: ExtractionLimits,
+) -> Result<()> {
+    let mut budget = Budget::new(limits);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+
+        if kind.is_pax_global_extensions() {
+            continue;
+        }
+
+        let path = validate_entry_path(&entry.path()?)?;
+        budget.add(entry.size())?;
+
+        if kind.is_symlink() || kind.is_hard_link() {
+            let Some(link) = entry.link_name()? else {
+                bail!("link {} has no target", path.display());
+            };
+
+            if kind.is_symlink() {
+                validate_symlink(&path, &link)?;
+            } else {
+                validate_entry_path(&link)?;
+            }
+        } else if kind.is_character_special() || kind.is_block_special() || kind.is_fifo() {
+            bail!("archive entry {} is not a regular file", path.display());
+        }
+
+        if !entry.unpack_in(dest)? {
+            bail!(
+                "archive entry {} escapes the extraction root",
+                path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes sure that writing to `path` doesn't go through a symlink that leads outside of `root`,
+/// which has to be canonical.
+///
+/// The entry paths themselves are validated beforehand, but an earlier symlink in the same archive
+/// can still redirect a later entry whose path looks harmless.
+fn ensure_inside(root: &Path, path: &Path) -> Result<()> {
+    let Some(existing) = path
+        .ancestors()
+        .find(|ancestor| ancestor.symlink_metadata().is_ok())
+    else {
+        return Ok(());
+    };
+
+    if !existing
+        .canonicalize()
+        .is_ok_and(|resolved| resolved.starts_with(root))
+    {
+        bail!(
+            "archive entry {} escapes the extraction root",
+            path.strip_prefix(root).unwrap_or(path).display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Makes sure that every symlink in `dir` resolves to a path inside `root`, which has to be canonical.
+///
+/// Symlinks are validated one by one as they are extracted, but chains of them can still end up
+/// outside of the extraction root, as can links that are moved when the root is stripped.
+#[async_recursion]
+async fn validate_links(root: &Path, dir: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(dir).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let ty = entry.file_type().await?;
+
+        if ty.is_symlink() {
+            if !fs::canonicalize(&path)
+                .await
+                .is_ok_and(|resolved| resolved.starts_with(root))
+            {
+                bail!(
+                    "symlink {} points outside of the extraction root",
+                    path.strip_prefix(root).unwrap_or(&path).display()
+                );
+            }
+        } else if ty.is_dir() {
+            validate_links(root, &path).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Validates and unpacks every entry of a zip archive into `dest`.
+///
+/// Entries are extracted manually instead of through [`ZipArchive::extract`] so that their actual
+/// uncompressed size can be checked against the size declared in the archive.
+fn unpack_zip(reader: impl Read + Seek, dest: &Path, limits: ExtractionLimits) -> Result<()> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut budget = Budget::new(limits);
+    let dest = dest.canonicalize()?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+
+        let path = validate_entry_path(Path::new(file.name()))?;
+        budget.add(file.size())?;
+
+        let out = dest.join(&path);
+        ensure_inside(&dest, &out)?;
+
+        if file.is_dir() {
+            std::fs::create_dir_all(&out)?;
+            continue;
+        }
+
+        if let Some(parent) = out.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        if file.is_symlink() {
+            let mut link = String::new();
+            file.read_to_string(&mut link)?;
+
+            validate_symlink(&path, Path::new(&link))?;
+            create_symlink(Path::new(&link), &out)?;
+            continue;
+        }
+
+        let declared = file.size();
+        let mut out_file = std::fs::File::create(&out)?;
+
+        if io::copy(&mut file.by_ref().take(declared + 1), &mut out_file)? > declared {
+            bail!(
+                "archive entry {} is larger than its declared size",
+                path.display()
+            );
+        }
+
+        #[cfg(unix)]
+        if let Some(mode) = file.unix_mode() {
+            use std::os::unix::fs::PermissionsExt as _;
+            out_file.set_permissions(std::fs::Permissions::from_mode(mode & 0o777))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpacks an archive from a stream into `dest`. Zip archives are not supported, since they require seeking.
+fn unpack_stream(
+    reader: impl BufRead,
+    format: Format,
+    dest: &Path,
+    limits: ExtractionLimits,
+) -> Result<()> {
+    match format {
+        Format::Zip => bail!("zip archives cannot be extracted from a stream"),
+        Format::Tar => unpack_tar(tar::Archive::new(reader), dest, limits),
+        Format::TarGz => unpack_tar(tar_stream(GzDecoder::new(reader))?, dest, limits),
+        Format::TarXz => unpack_tar(tar_stream(XzDecoder::new(reader))?, dest, limits),
+        Format::TarBz2 => unpack_tar(tar_stream(BzDecoder::new(reader))?, dest, limits),
+        Format::TarZst => unpack_tar(tar_stream(ZstdDecoder::with_buffer(reader)?)?, dest, limits),
+    }
+}
+
+fn unpack_file(path: &Path, format: Format, dest: &Path, limits: ExtractionLimits) -> Result<()> {
+    let reader = BufReader::new(std::fs::File::open(path)?);
+
+    if format == Format::Zip {
+        unpack_zip(reader, dest, limits)
+    } else {
+        unpack_stream(reader, format, dest, limits)
+    }
+}
+
+/// Moves an extracted archive into `target`, collapsing a single top-level directory if `strip` is set.
+async fn install(extracted: &Path, target: &Path, strip: bool) -> Result<()> {
+    if strip {
+        strip_root(extracted).await?;
+    }
+
+    let root = fs::canonicalize(extracted).await?;
+    validate_links(&root, &root).await?;
+
+    super::copy_dir_all(extracted, target).await?;
+    fs::remove_dir_all(extracted).await?;
+
+    Ok(())
+}
+
+/// Extracts an archive file on disk into `target`.
+///
+/// If `strip` is set, a single top-level directory in the archive is collapsed.
+pub async fn extract_file(
+    path: &Path,
+    format: Format,
+    target: &Path,
+    strip: bool,
+    limits: ExtractionLimits,
+) -> Result<()> {
+    let temp_extract_dir = TempDir::new()?;
+
+    let (path_owned, dest) = (path.to_owned(), temp_extract_dir.path().to_owned());
+    task::spawn_blocking(move || unpack_file(&path_owned, format, &dest, limits)).await??;
+
+    install(temp_extract_dir.path(), target, strip).await
+}
+
+/// Verifies an archive file on disk against every entry in `integrity` and extracts it into `target`,
+/// returning the hex-encoded SHA-256 of the archive.
+///
+/// The format is detected from the contents of the file, falling back to `hint`.
+pub async fn verify_and_extract_file(
+    path: &Path,
+    hint: Option<Format>,
+    target: &Path,
+    integrity: &[Integrity],
+    limits: ExtractionLimits,
+) -> Result<String> {
+    let hashes = integrity::hash_file(path).await?;
+    integrity::verify_all(integrity, &hashes)?;
+
+    let format = Format::sniff_file(path)?
+        .or(hint)
+        .ok_or_else(|| eyre!("could not detect archive format of {}", path.display()))?;
+
+    extract_file(path, format, target, true, limits).await?;
+
+    Ok(hashes.sha256_hex())
+}
+
+/// A [`Read`] implementation that receives its data from an asynchronous task.
+struct ChannelReader {
+    rx: mpsc::Receiver<Bytes>,
+    buf: Bytes,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.buf.is_empty() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => self.buf = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let n = out.len().min(self.buf.len());
+        out[..n].copy_from_slice(&self.buf.split_to(n));
+
+        Ok(n)
+    }
+}
+
+/// Extracts a tar-based archive incrementally as its data arrives.
+pub struct StreamExtractor {
+    tx: Option<mpsc::Sender<Bytes>>,
+    handle: task::JoinHandle<Result<()>>,
+    temp_extract_dir: TempDir,
+}
+
+impl StreamExtractor {
+    pub fn new(format: Format, limits: ExtractionLimits) -> Result<Self> {
+        let temp_extract_dir = TempDir::new()?;
+        let dest = temp_extract_dir.path().to_owned();
+
+        // a bounded channel keeps memory usage constant even if extraction is slower than the download
+        let (tx, rx) = mpsc::channel(16);
+
+        let handle = task::spawn_blocking(move || {
+            let reader = BufReader::new(ChannelReader {
+                rx,
+                buf: Bytes::new(),
+            });
+
+            unpack_stream(reader, format, &dest, limits)
+        });
+
+        Ok(Self {
+            tx: Some(tx),
+            handle,
+            temp_extract_dir,
+        })
+    }
+
+    pub async fn write(&mut self, chunk: Bytes) {
+        if let Some(tx) = &self.tx
+            && tx.send(chunk).await.is_err()
+        {
+            // the extractor has stopped reading, either because it is done or because it failed;
+            // either way, the outcome is reported by `finish`
+            self.tx = None;
+        }
+    }
+
+    /// Stops extraction, discarding everything that has been extracted so far.
+    pub async fn abort(mut self) {
+        self.tx = None;
+        let _ = self.handle.await;
+    }
+
+    /// Waits for extraction to complete and installs the extracted files into `target`.
+    pub async fn finish(mut self, target: &Path, strip: bool) -> Result<()> {
+        self.tx = None;
+        self.handle.await??;
+
+        install(self.temp_extract_dir.path(), target, strip).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write as _;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    #[test]
+    fn entry_paths() {
+        assert_eq!(
+            validate_entry_path(Path::new("a/./b.css")).unwrap(),
+            Path::new("a/b.css")
+        );
+
+        for path in ["../a", "a/../../b", "/etc/passwd", ".", ""] {
+            assert!(validate_entry_path(Path::new(path)).is_err(), "{path}");
+        }
+    }
+
+    #[test]
+    fn symlinks() {
+        for (path, target) in [("a/b/l", "../c"), ("a/l", "b/c"), ("l", "a/./b")] {
+            assert!(
+                validate_symlink(Path::new(path), Path::new(target)).is_ok(),
+                "{path} -> {target}"
+            );
+        }
+
+        for (path, target) in [
+            ("l", ".."),
+            ("a/l", "../../x"),
+            ("a/l", "/etc/passwd"),
+            ("a/l", "b/../../.."),
+        ] {
+            assert!(
+                validate_symlink(Path::new(path), Path::new(target)).is_err(),
+                "{path} -> {target}"
+            );
+        }
+    }
+
+    fn symlink_header(target: &str) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_link_name(target).unwrap();
+        header.set_size(0);
+        header.set_mode(0o777);
+        header
+    }
+
+    fn file_header(size: usize) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(size as u64);
+        header.set_mode(0o644);
+        header
+    }
+
+    #[test]
+    fn zip_chained_symlinks() {
+        let temp = TempDir::new().unwrap();
+        let dest = temp.path().join("dest");
+        std::fs::create_dir(&dest).unwrap();
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        writer.add_symlink("a/b/l1", "..", options).unwrap();
+        writer.add_symlink("a/b/l1/l2", "../..", options).unwrap();
+        writer.start_file("a/l2/PWNED", options).unwrap();
+        writer.write_all(b"pwned").unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        assert!(unpack_zip(Cursor::new(data), &dest, ExtractionLimits::default()).is_err());
+        assert!(!temp.path().join("PWNED").exists());
+    }
+
+    #[tokio::test]
+    async fn tar_chained_symlinks() {
+        let temp = TempDir::new().unwrap();
+        let (archive, target) = (temp.path().join("theme.tar"), temp.path().join("chrome"));
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder
+            .append_data(&mut symlink_header(".."), "a/b/l1", io::empty())
+            .unwrap();
+        builder
+            .append_data(&mut symlink_header("../.."), "a/b/l1/l2", io::empty())
+            .unwrap();
+        std::fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+
+        let result = extract_file(
+            &archive,
+            Format::Tar,
+            &target,
+            true,
+            ExtractionLimits::default(),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert!(!target.exists());
+    }
+
+    #[tokio::test]
+    async fn tar_internal_symlinks() {
+        let temp = TempDir::new().unwrap();
+        let (archive, target) = (temp.path().join("theme.tar"), temp.path().join("chrome"));
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder
+            .append_data(&mut file_header(5), "theme/a.css", &b"a {}\n"[..])
+            .unwrap();
+        builder
+            .append_data(&mut symlink_header("a.css"), "theme/b.css", io::empty())
+            .unwrap();
+        std::fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+
+        extract_file(
+            &archive,
+            Format::Tar,
+            &target,
+            true,
+            ExtractionLimits::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(target.join("b.css").is_symlink());
+        assert_eq!(std::fs::read(target.join("b.css")).unwrap(), b"a {}\n");
+    }
+}
@@ -0,0 +1,65 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use eyre::Result;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use url::Url;
+
+use crate::config::get_cache_dir;
+
+use super::integrity::sha256_hex;
+
+static LOCKS: LazyLock<Mutex<HashMap<PathBuf, Arc<AsyncMutex<()>>>>> =
+    LazyLock::new(Mutex::default);
+
+fn key(url: &Url) -> String {
+    sha256_hex(url.as_str().as_bytes())
+}
+
+/// Returns the path of the bare repository that caches objects fetched from a Git remote.
+pub fn git_repo(url: &Url) -> Result<PathBuf> {
+    Ok(get_cache_dir()?.join("git").join(key(url)))
+}
+
+/// Returns the path of the cached archive (or single file) downloaded from a URL.
+///
+/// The parent directory is created if it does not exist yet.
+pub fn archive_path(url: &Url) -> Result<PathBuf> {
+    let dir = get_cache_dir()?.join("archives");
+    std::fs::create_dir_all(&dir)?;
+
+    Ok(dir.join(key(url)))
+}
+
+/// Returns the path that an archive is written to while it is being downloaded.
+///
+/// Downloads are only moved into place once they complete, so the cache never contains truncated archives.
+pub fn part_path(path: &Path) -> PathBuf {
+    path.with_extension("part")
+}
+
+/// Returns the path that the `ETag` or `Last-Modified` of a partial download is stored at,
+/// so that it is only resumed if the remote file has not changed in the meantime.
+pub fn validator_path(path: &Path) -> PathBuf {
+    path.with_extension("validator")
+}
+
+/// Locks a cache entry for the lifetime of the returned guard, so that concurrent retrievals of the
+/// same source don't write to it at the same time.
+pub async fn lock(path: &Path) -> OwnedMutexGuard<()> {
+    let mutex = LOCKS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(path.to_owned())
+        .or_default()
+        .clone();
+
+    mutex.lock_owned().await
+}
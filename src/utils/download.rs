@@ -3,10 +3,16 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    io::{BufReader, Cursor},
-    path::Path,
+    fmt::Write as _,
+    io::{self, IsTerminal as _, SeekFrom},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _},
 };
-use tokio::fs;
 use tokio_stream::StreamExt as _;
 
 use anstream::{eprint, eprintln, stderr};
@@ -14,135 +20,1073 @@ use bytesize::ByteSize;
 use crossterm::{ExecutableCommand as _, cursor, terminal};
 use eyre::{Result, bail, eyre};
 use owo_colors::OwoColorize as _;
-use temp_dir::TempDir;
-
-use bzip2::bufread::BzDecoder;
-use flate2::bufread::GzDecoder;
-use xz2::bufread::XzDecoder;
-use zip::ZipArchive;
-use zstd::stream::Decoder as ZstdDecoder;
 
+use bytes::Bytes;
+use percent_encoding::percent_decode_str;
+use reqwest::{
+    RequestBuilder, Response, StatusCode,
+    header::{self, HeaderMap, HeaderValue},
+};
 use url::Url;
 
-async fn strip_root(dir: &Path) -> Result<()> {
-    let mut entries = fs::read_dir(dir).await?;
+use super::{
+    archive::{self, Format, StreamExtractor},
+    cache,
+    integrity::{self, Hasher, Hashes},
+};
+use crate::{
+    config::{ExtractionLimits, NetworkConfig},
+    source::RetrieveOptions,
+};
+
+/// Number of times a request is retried after a transient failure.
+const MAX_RETRIES: u32 = 5;
+/// Delay before the first retry, which doubles with every subsequent retry.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Upper bound for delays requested by servers through `Retry-After`.
+const MAX_RETRY_AFTER: Duration = Duration::from_mins(1);
+
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// Returns the first non-empty environment variable out of `vars`.
+fn env_var(vars: &[&str]) -> Option<String> {
+    vars.iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+}
+
+/// Creates the proxies configured through environment variables, excluding the hosts in `no_proxy`
+/// in addition to those in `NO_PROXY`.
+///
+/// Configuring any proxy on the client disables the ones from the environment, so they have to be
+/// recreated for the hosts in `no_proxy` to be excluded from them.
+fn env_proxies(no_proxy: &[String]) -> Result<Vec<reqwest::Proxy>> {
+    let no_proxy = env_var(&["NO_PROXY", "no_proxy"])
+        .into_iter()
+        .chain(no_proxy.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let configure = |url: String, proxy: reqwest::Result<reqwest::Proxy>| {
+        proxy
+            .map(|proxy| proxy.no_proxy(reqwest::NoProxy::from_string(&no_proxy)))
+            .map_err(|e| eyre!("invalid proxy {url:?}: {e}"))
+    };
+
+    let mut proxies = Vec::new();
+
+    if let Some(url) = env_var(&["HTTP_PROXY", "http_proxy"]) {
+        proxies.push(configure(url.clone(), reqwest::Proxy::http(url))?);
+    }
+    if let Some(url) = env_var(&["HTTPS_PROXY", "https_proxy"]) {
+        proxies.push(configure(url.clone(), reqwest::Proxy::https(url))?);
+    }
+    if let Some(url) = env_var(&["ALL_PROXY", "all_proxy"]) {
+        proxies.push(configure(url.clone(), reqwest::Proxy::all(url))?);
+    }
+
+    Ok(proxies)
+}
+
+fn build_client(config: &NetworkConfig) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(config.user_agent.as_deref().unwrap_or(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        )))
+        .connect_timeout(Duration::from_secs(30))
+        .read_timeout(Duration::from_mins(1));
+
+    if let Some(proxy) = &config.proxy {
+        let no_proxy = reqwest::NoProxy::from_string(&config.no_proxy.join(","));
+
+        builder = builder.proxy(
+            reqwest::Proxy::all(proxy)
+                .map_err(|e| eyre!("invalid proxy {proxy:?}: {e}"))?
+                .no_proxy(no_proxy),
+        );
+    } else if !config.no_proxy.is_empty() {
+        builder = env_proxies(&config.no_proxy)?
+            .into_iter()
+            .fold(builder, reqwest::ClientBuilder::proxy);
+    }
+
+    for path in &config.ca_certificates {
+        let pem = std::fs::read(path)
+            .map_err(|e| eyre!("failed to read CA certificate {}: {e}", path.display()))?;
+
+        for certificate in reqwest::Certificate::from_pem_bundle(&pem)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Builds the HTTP client used for all requests from the network settings in the config.
+///
+/// This has to be called before the first request is made, since the client is created lazily otherwise.
+pub fn configure(config: &NetworkConfig) -> Result<()> {
+    CLIENT
+        .set(build_client(config)?)
+        .map_err(|_| eyre!("the HTTP client has already been configured"))
+}
+
+/// Returns the HTTP client that is shared by all requests.
+pub fn client() -> &'static reqwest::Client {
+    CLIENT.get_or_init(|| build_client(&NetworkConfig::default()).unwrap())
+}
+
+fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF * 2_u32.pow(attempt)
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+fn is_transient_error(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect() || err.is_request() || err.is_body() || err.is_decode()
+}
+
+/// Parses the `Retry-After` header of a response, which is either a number of seconds or an HTTP date.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    let delay = value.parse().map(Duration::from_secs).ok().or_else(|| {
+        let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .ok()
+    })?;
+
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// Describes an exhausted API rate limit, which is reported as a `403` or `429` response with
+/// `x-ratelimit-remaining: 0` (GitHub only allows 60 unauthenticated requests per hour).
+fn rate_limit_error(resp: &Response, authenticated: bool) -> Option<eyre::Report> {
+    let headers = resp.headers();
+
+    if !matches!(
+        resp.status(),
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS
+    ) || headers
+        .get("x-ratelimit-remaining")
+        .is_none_or(|v| v != "0")
+    {
+        return None;
+    }
+
+    let host = resp.url().host_str().unwrap_or_default();
+    let mut message = if host == "api.github.com" {
+        "GitHub API rate limit exceeded".to_owned()
+    } else {
+        format!("API rate limit of {host} exceeded")
+    };
+
+    if let Some(reset) = headers
+        .get("x-ratelimit-reset")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok())
+    {
+        let secs = (reset - chrono::Utc::now().timestamp())
+            .max(0)
+            .unsigned_abs();
+        let _ = write!(message, ", resets in {}", format_eta(secs));
+    }
+
+    if !authenticated {
+        if host == "api.github.com" {
+            message.push_str(
+                "; set `GITHUB_TOKEN` or add a token for github.com to `tokens` in your config to raise it",
+            );
+        } else {
+            let _ = write!(
+                message,
+                "; add a token for {host} to `tokens` in your config to raise it"
+            );
+        }
+    }
+
+    Some(eyre!(message))
+}
+
+pub trait RequestBuilderExt {
+    /// Sends the request, retrying with exponential backoff on connection errors and on
+    /// `5xx`, `408` and `429` responses, honoring `Retry-After`.
+    ///
+    /// Fails without retrying if an API rate limit was exhausted.
+    async fn send_with_retry(self) -> Result<Response>;
+}
+
+impl RequestBuilderExt for RequestBuilder {
+    async fn send_with_retry(self) -> Result<Response> {
+        let mut attempt = 0;
+
+        loop {
+            let (client, request) = self
+                .try_clone()
+                .ok_or_else(|| eyre!("request cannot be retried"))?
+                .build_split();
+            let request = request?;
+            let authenticated = request.headers().contains_key(header::AUTHORIZATION);
+
+            let delay = match client.execute(request).await {
+                Ok(resp) if let Some(err) = rate_limit_error(&resp, authenticated) => {
+                    return Err(err);
+                }
+                Ok(resp) if is_transient_status(resp.status()) && attempt < MAX_RETRIES => {
+                    retry_after(&resp).unwrap_or_else(|| backoff(attempt))
+                }
+                Ok(resp) => return Ok(resp),
+                Err(err) if is_transient_error(&err) && attempt < MAX_RETRIES => backoff(attempt),
+                Err(err) => return Err(err.into()),
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Extracts the file name from a `Content-Disposition` header value.
+fn disposition_file_name(value: &str) -> Option<String> {
+    let params = value.split(';').map(str::trim).collect::<Vec<_>>();
+
+    params
+        .iter()
+        .find_map(|p| p.strip_prefix("filename*="))
+        .and_then(|v| v.split_once("''"))
+        .and_then(|(_, name)| percent_decode_str(name).decode_utf8().ok())
+        .map(|name| name.into_owned())
+        .or_else(|| {
+            params
+                .iter()
+                .find_map(|p| p.strip_prefix("filename="))
+                .map(|name| name.trim_matches('"').to_owned())
+        })
+}
+
+/// How often progress is redrawn when stderr is a terminal.
+const DRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// How often progress is logged when stderr is not a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProgressStyle {
+    /// Redraw the progress in place after the URL
+    Terminal,
+    /// Log the progress on separate lines periodically, for CI logs and piped output
+    Plain,
+    Hidden,
+}
+
+fn format_eta(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Displays the progress of a download after its URL, including the percentage, throughput and
+/// remaining time if the total size is known.
+struct Progress {
+    url: Url,
+    style: ProgressStyle,
+    /// Position in the archive
+    received: u64,
+    /// Total size of the archive, if known
+    total: Option<u64>,
+    /// Number of bytes transferred in this run, which excludes data resumed from an earlier run
+    transferred: u64,
+    started: Instant,
+    last_report: Instant,
+}
 
-    let first = entries.next_entry().await?;
-    let only_entry = entries.next_entry().await?.is_none();
+impl Progress {
+    fn start(url: &Url, quiet: bool) -> Result<Self> {
+        let style = if quiet {
+            ProgressStyle::Hidden
+        } else if io::stderr().is_terminal() {
+            ProgressStyle::Terminal
+        } else {
+            ProgressStyle::Plain
+        };
 
-    if let Some(first) = first {
-        if only_entry && first.path().is_dir() {
-            let subdirectory = first.path();
+        if style == ProgressStyle::Terminal {
+            eprint!("{} {}  ", "╰".cyan().dimmed(), url.dimmed());
+            stderr().execute(cursor::SavePosition)?;
+        }
+
+        let now = Instant::now();
+
+        Ok(Self {
+            url: url.to_owned(),
+            style,
+            received: 0,
+            total: None,
+            transferred: 0,
+            started: now,
+            last_report: now,
+        })
+    }
 
-            let mut sub_entries = fs::read_dir(&subdirectory).await?;
-            while let Some(from) = sub_entries.next_entry().await? {
-                let to = dir.join(from.file_name());
-                fs::rename(from.path(), &to).await?;
+    fn set_total(&mut self, total: Option<u64>) {
+        self.total = total;
+    }
+
+    /// Bytes transferred per second in this run.
+    fn rate(&self) -> u64 {
+        let millis = self.started.elapsed().as_millis();
+        if millis == 0 {
+            return 0;
+        }
+
+        u64::try_from(u128::from(self.transferred) * 1000 / millis).unwrap_or(u64::MAX)
+    }
+
+    fn status(&self) -> String {
+        let mut status = ByteSize::b(self.received).display().si().to_string();
+
+        if let Some(total) = self.total.filter(|total| *total > 0) {
+            let percent = self.received.min(total) * 100 / total;
+            let _ = write!(
+                status,
+                " / {} ({percent}%)",
+                ByteSize::b(total).display().si()
+            );
+        }
+
+        let rate = self.rate();
+        if rate > 0 {
+            let _ = write!(status, " · {}/s", ByteSize::b(rate).display().si());
+
+            if let Some(total) = self.total.filter(|total| *total > self.received) {
+                let _ = write!(
+                    status,
+                    " · {} left",
+                    format_eta((total - self.received).div_ceil(rate))
+                );
             }
+        }
+
+        status
+    }
+
+    fn update(&mut self, received: u64, len: usize) -> Result<()> {
+        self.received = received;
+        self.transferred += len as u64;
+
+        let interval = match self.style {
+            ProgressStyle::Terminal => DRAW_INTERVAL,
+            ProgressStyle::Plain => LOG_INTERVAL,
+            ProgressStyle::Hidden => return Ok(()),
+        };
 
-            fs::remove_dir(subdirectory).await?;
+        if self.last_report.elapsed() < interval {
+            return Ok(());
         }
+
+        self.last_report = Instant::now();
+
+        if self.style == ProgressStyle::Terminal {
+            stderr()
+                .execute(cursor::RestorePosition)?
+                .execute(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+
+            eprint!("{}", self.status().cyan().dimmed());
+        } else {
+            eprintln!(
+                "{} {} {}",
+                "╰".cyan().dimmed(),
+                self.url.dimmed(),
+                self.status().cyan().dimmed()
+            );
+        }
+
+        Ok(())
     }
 
-    Ok(())
+    fn finish(self) -> Result<()> {
+        let size = ByteSize::b(self.received).display().si().to_string();
+
+        match self.style {
+            ProgressStyle::Terminal => {
+                stderr()
+                    .execute(cursor::RestorePosition)?
+                    .execute(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+
+                eprintln!("{}", size.green().dimmed());
+            }
+            ProgressStyle::Plain => {
+                eprintln!(
+                    "{} {} {}",
+                    "╰".cyan().dimmed(),
+                    self.url.dimmed(),
+                    size.green().dimmed()
+                );
+            }
+            ProgressStyle::Hidden => {}
+        }
+
+        Ok(())
+    }
 }
 
-pub async fn archive(url: &Url, target: &Path) -> Result<()> {
-    let ext = Path::new(url.path())
-        .extension()
-        .and_then(|s| s.to_str())
-        .ok_or_else(|| eyre!("could not infer file extension"))?;
+/// Receives the data of a download, persisting it to a partial file in the cache while hashing it
+/// and, if it is an archive, extracting it on the fly.
+struct Sink {
+    part_path: PathBuf,
+    part: fs::File,
+    /// `ETag` or `Last-Modified` of the partial file, which is persisted alongside it
+    validator: Option<HeaderValue>,
+    /// Number of bytes in the partial file
+    written: u64,
+    /// Number of bytes that have been hashed and extracted, which lags behind `written`
+    /// when resuming a download from an earlier run
+    consumed: u64,
+    hasher: Hasher,
+    /// Beginning of the archive, buffered until the format can be detected
+    head: Vec<u8>,
+    /// Format to fall back to if it cannot be detected from the contents of the archive
+    hint: Option<Format>,
+    format: Option<Format>,
+    extractor: Option<StreamExtractor>,
+    /// Limits for extracting the download, or `None` if it is not an archive
+    limits: Option<ExtractionLimits>,
+}
 
-    eprint!("{} {}  ", "╰".cyan().dimmed(), url.dimmed());
+impl Sink {
+    /// Opens the partial file at `part_path`, keeping any data left over from an earlier download
+    /// if its validator was stored along with it.
+    async fn open(
+        part_path: &Path,
+        hint: Option<Format>,
+        limits: Option<ExtractionLimits>,
+    ) -> Result<Self> {
+        let part = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(part_path)
+            .await?;
 
-    stderr().execute(cursor::SavePosition)?;
+        let validator = fs::read(cache::validator_path(part_path))
+            .await
+            .ok()
+            .and_then(|value| HeaderValue::from_bytes(&value).ok());
 
-    let mut resp = reqwest::get(url.to_owned())
-        .await?
-        .error_for_status()?
-        .bytes_stream();
+        // without a validator, there is no way to tell whether the remote file has changed
+        if validator.is_none() {
+            part.set_len(0).await?;
+        }
 
-    let mut data: Vec<u8> = Vec::new();
+        let written = part.metadata().await?.len();
 
-    while let Some(chunk) = resp.next().await {
-        data.extend(chunk?);
+        Ok(Self {
+            part_path: part_path.to_owned(),
+            part,
+            validator,
+            written,
+            consumed: 0,
+            hasher: Hasher::default(),
+            head: Vec::new(),
+            hint,
+            format: None,
+            extractor: None,
+            limits,
+        })
+    }
 
-        stderr()
-            .execute(cursor::RestorePosition)?
-            .execute(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+    fn detect(&mut self) -> Result<()> {
+        let Some(limits) = self.limits else {
+            return Ok(());
+        };
 
-        eprint!(
-            "{}",
-            ByteSize::b(data.len() as u64)
-                .display()
-                .si()
-                .cyan()
-                .dimmed(),
-        );
+        let format = Format::sniff(&self.head)
+            .or(self.hint)
+            .ok_or_else(|| eyre!("could not detect archive format"))?;
+
+        self.format = Some(format);
+
+        if format != Format::Zip {
+            self.extractor = Some(StreamExtractor::new(format, limits)?);
+        }
+
+        Ok(())
+    }
+
+    async fn consume(&mut self, chunk: Bytes) -> Result<()> {
+        self.hasher.update(&chunk);
+        self.consumed += chunk.len() as u64;
+
+        if self.limits.is_none() {
+            return Ok(());
+        }
+
+        if self.format.is_none() {
+            self.head.extend_from_slice(&chunk);
+
+            if self.head.len() < archive::TAR_BLOCK_SIZE {
+                return Ok(());
+            }
+
+            self.detect()?;
+
+            let head = Bytes::from(std::mem::take(&mut self.head));
+            if let Some(extractor) = &mut self.extractor {
+                extractor.write(head).await;
+            }
+        } else if let Some(extractor) = &mut self.extractor {
+            extractor.write(chunk).await;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&mut self, chunk: Bytes) -> Result<()> {
+        self.part.write_all(&chunk).await?;
+        self.written += chunk.len() as u64;
+
+        self.consume(chunk).await
+    }
+
+    /// Hashes and extracts the data that was downloaded in an earlier run, so that the download can be resumed.
+    async fn catch_up(&mut self) -> Result<()> {
+        if self.consumed == self.written {
+            return Ok(());
+        }
+
+        let mut file = fs::File::open(&self.part_path).await?;
+        file.seek(SeekFrom::Start(self.consumed)).await?;
+
+        let mut remaining = self.written - self.consumed;
+        let mut buf = vec![0; 64 * 1024];
+
+        while remaining > 0 {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                bail!("{} was truncated", self.part_path.display());
+            }
+
+            let n = n.min(usize::try_from(remaining).unwrap_or(usize::MAX));
+            remaining -= n as u64;
+
+            self.consume(Bytes::copy_from_slice(&buf[..n])).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the validator of the remote file that is being downloaded.
+    async fn set_validator(&mut self, validator: Option<HeaderValue>) -> Result<()> {
+        if validator == self.validator {
+            return Ok(());
+        }
+
+        let path = cache::validator_path(&self.part_path);
+
+        match &validator {
+            Some(value) => fs::write(&path, value.as_bytes()).await?,
+            None if path.exists() => fs::remove_file(&path).await?,
+            None => {}
+        }
+
+        self.validator = validator;
+
+        Ok(())
+    }
+
+    /// Discards everything that has been downloaded so far.
+    async fn reset(&mut self) -> Result<()> {
+        self.part.set_len(0).await?;
+
+        if let Some(extractor) = self.extractor.take() {
+            extractor.abort().await;
+        }
+
+        self.written = 0;
+        self.consumed = 0;
+        self.hasher = Hasher::default();
+        self.head.clear();
+        self.format = None;
+
+        Ok(())
+    }
+
+    async fn finish(mut self) -> Result<(Hashes, Option<Format>, Option<StreamExtractor>)> {
+        self.part.flush().await?;
+
+        if self.format.is_none() {
+            self.detect()?;
+
+            let head = Bytes::from(std::mem::take(&mut self.head));
+            if let Some(extractor) = &mut self.extractor {
+                extractor.write(head).await;
+            }
+        }
+
+        Ok((self.hasher.finalize(), self.format, self.extractor))
     }
+}
 
-    stderr()
-        .execute(cursor::RestorePosition)?
-        .execute(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+/// Returns the offset that a `206 Partial Content` response starts at.
+fn content_range_start(resp: &Response) -> Option<u64> {
+    resp.headers()
+        .get(header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .parse()
+        .ok()
+}
 
-    eprintln!(
-        "{}",
-        ByteSize::b(data.len() as u64)
-            .display()
-            .si()
-            .green()
-            .dimmed(),
-    );
+/// Detects the archive format from the `Content-Disposition` and `Content-Type` headers of a response.
+fn header_format(resp: &Response) -> Option<Format> {
+    resp.headers()
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(disposition_file_name)
+        .and_then(|name| Format::from_path(Path::new(&name)).ok())
+        .or_else(|| {
+            resp.headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(Format::from_mime)
+        })
+}
 
-    let reader = BufReader::new(Cursor::new(data));
+/// Downloads `url` into `sink` with additional `headers`, retrying and resuming the download if the connection is interrupted.
+async fn download(
+    url: &Url,
+    headers: &HeaderMap,
+    sink: &mut Sink,
+    progress: &mut Progress,
+) -> Result<()> {
+    let mut attempt = 0;
 
-    let temp_extract_dir = TempDir::new()?;
-    let temp_extract_path = temp_extract_dir.path();
+    loop {
+        let mut request = client().get(url.to_owned()).headers(headers.clone());
 
-    match ext {
-        "zip" => {
-            let mut zip = ZipArchive::new(reader)?;
-            zip.extract(temp_extract_path)?;
+        // the validator makes sure that the remote file has not changed since the partial file was written,
+        // since a server that doesn't match it responds with the whole file instead
+        if sink.written > 0
+            && let Some(validator) = &sink.validator
+        {
+            request = request
+                .header(header::RANGE, format!("bytes={}-", sink.written))
+                .header(header::IF_RANGE, validator);
         }
 
-        "tar" => {
-            let mut archive = tar::Archive::new(reader);
-            archive.unpack(temp_extract_path)?;
+        let resp = request.send_with_retry().await?;
+
+        if resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            sink.reset().await?;
+            continue;
         }
 
-        "gz" | "tgz" => {
-            let gz = GzDecoder::new(reader);
-            let mut archive = tar::Archive::new(gz);
-            archive.unpack(temp_extract_path)?;
+        let resp = resp.error_for_status()?;
+
+        if let Some(format) = header_format(&resp) {
+            sink.hint = Some(format);
         }
 
-        "xz" => {
-            let xz = XzDecoder::new(reader);
-            let mut archive = tar::Archive::new(xz);
-            archive.unpack(temp_extract_path)?;
+        if resp.status() == StatusCode::PARTIAL_CONTENT
+            && content_range_start(&resp) == Some(sink.written)
+        {
+            sink.catch_up().await?;
+        } else {
+            sink.reset().await?;
         }
 
-        "bz2" => {
-            let bz = BzDecoder::new(reader);
-            let mut archive = tar::Archive::new(bz);
-            archive.unpack(temp_extract_path)?;
+        sink.set_validator(
+            resp.headers()
+                .get(header::ETAG)
+                .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+                .or_else(|| resp.headers().get(header::LAST_MODIFIED))
+                .cloned(),
+        )
+        .await?;
+
+        progress.set_total(resp.content_length().map(|len| sink.written + len));
+
+        let mut stream = resp.bytes_stream();
+
+        let result = loop {
+            match stream.next().await {
+                Some(Ok(chunk)) => {
+                    let len = chunk.len();
+                    sink.write(chunk).await?;
+                    progress.update(sink.written, len)?;
+                }
+                Some(Err(err)) => break Err(err),
+                None => break Ok(()),
+            }
+        };
+
+        match result {
+            Ok(()) => return Ok(()),
+            Err(err) if is_transient_error(&err) && attempt < MAX_RETRIES => {
+                tokio::time::sleep(backoff(attempt)).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err.into()),
         }
+    }
+}
+
+/// Checks whether the cached download of `url` can be used instead of downloading it again,
+/// which is the case if it matches the expected integrity, or in offline mode.
+async fn use_cached(url: &Url, cache_path: &Path, options: &RetrieveOptions) -> Result<bool> {
+    if !cache_path.exists() {
+        if options.offline {
+            bail!("{url} is not available in the cache");
+        }
+
+        return Ok(false);
+    }
+
+    let digests = integrity::hash_file(cache_path).await?;
+
+    let usable = options.offline
+        || (!options.integrity.is_empty()
+            && integrity::verify_all(&options.integrity, &digests).is_ok());
+
+    if usable && !options.quiet {
+        eprintln!(
+            "{} {} {}",
+            "╰".cyan().dimmed(),
+            url.dimmed(),
+            "(cached)".green().dimmed()
+        );
+    }
+
+    Ok(usable)
+}
+
+/// Downloads `url` into the cache and verifies it against every entry in `options.integrity`.
+///
+/// If `limits` is set, the download is treated as an archive and extracted on the fly where possible.
+async fn fetch(
+    url: &Url,
+    cache_path: &Path,
+    limits: Option<ExtractionLimits>,
+    options: &RetrieveOptions,
+) -> Result<(Hashes, Option<Format>, Option<StreamExtractor>)> {
+    let part_path = cache::part_path(cache_path);
+    let url_format = Format::from_path(Path::new(url.path())).ok();
+
+    let mut sink = Sink::open(&part_path, url_format, limits).await?;
+    let mut progress = Progress::start(url, options.quiet)?;
 
-        "zst" => {
-            let zstd = ZstdDecoder::new(reader)?;
-            let mut archive = tar::Archive::new(zstd);
-            archive.unpack(temp_extract_path)?;
+    download(url, &options.headers, &mut sink, &mut progress).await?;
+    progress.finish()?;
+
+    let (digests, format, extractor) = sink
+        .finish()
+        .await
+        .map_err(|e| eyre!("failed to extract archive from {url}: {e}"))?;
+
+    let _ = fs::remove_file(cache::validator_path(&part_path)).await;
+
+    if let Err(err) = integrity::verify_all(&options.integrity, &digests) {
+        fs::remove_file(&part_path).await?;
+        bail!("failed to verify download from {url}: {err}");
+    }
+
+    fs::rename(&part_path, cache_path).await?;
+
+    Ok((digests, format, extractor))
+}
+
+/// Downloads and extracts an archive into `target`, returning the hex-encoded SHA-256 of the archive.
+///
+/// Tar-based archives are extracted while they are being downloaded, whereas zip archives are
+/// extracted from the cache once the download completes. Either way, the archive is verified
+/// against every entry in `options.integrity` before anything is installed into `target`.
+///
+/// Interrupted downloads are resumed with `Range` requests, both within a run and across runs.
+/// Previously downloaded archives are reused from the cache if they match the expected integrity,
+/// or unconditionally in offline mode.
+pub async fn archive(url: &Url, target: &Path, options: &RetrieveOptions) -> Result<String> {
+    let cache_path = cache::archive_path(url)?;
+    let _guard = cache::lock(&cache_path).await;
+
+    if use_cached(url, &cache_path, options).await? {
+        return archive::verify_and_extract_file(
+            &cache_path,
+            Format::from_path(Path::new(url.path())).ok(),
+            target,
+            &options.integrity,
+            options.limits,
+        )
+        .await
+        .map_err(|e| eyre!("failed to extract cached archive from {url}: {e}"));
+    }
+
+    let (digests, format, extractor) =
+        fetch(url, &cache_path, Some(options.limits), options).await?;
+
+    if let Some(extractor) = extractor {
+        extractor.finish(target, true).await?;
+    } else if let Some(format) = format {
+        archive::extract_file(&cache_path, format, target, true, options.limits).await?;
+    }
+
+    Ok(digests.sha256_hex())
+}
+
+/// Downloads a single file to `target`, returning its hex-encoded SHA-256.
+///
+/// Downloads are cached, verified and resumed in the same way as archives.
+pub async fn file(url: &Url, target: &Path, options: &RetrieveOptions) -> Result<String> {
+    let cache_path = cache::archive_path(url)?;
+    let _guard = cache::lock(&cache_path).await;
+
+    let digests = if use_cached(url, &cache_path, options).await? {
+        let digests = integrity::hash_file(&cache_path).await?;
+        integrity::verify_all(&options.integrity, &digests)?;
+        digests
+    } else {
+        fetch(url, &cache_path, None, options).await?.0
+    };
+
+    fs::copy(&cache_path, target).await?;
+
+    Ok(digests.sha256_hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+    use temp_dir::TempDir;
+    use tokio::net::TcpListener;
+
+    const ETAG: &str = "\"v1\"";
+
+    fn body() -> Vec<u8> {
+        (0..64 * 1024_u32)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect()
+    }
+
+    fn response(status: &str, headers: &[String], body: &[u8], length: usize) -> Vec<u8> {
+        let mut response =
+            format!("HTTP/1.1 {status}\r\nconnection: close\r\ncontent-length: {length}\r\n");
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
         }
+        response.push_str("\r\n");
+
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    /// Serves the responses returned by `respond` for each request, which is given the index and
+    /// the lowercased head of the request. Connections are closed after every response, so
+    /// responses with a body shorter than their `Content-Length` simulate a dropped connection.
+    async fn serve(
+        respond: impl Fn(usize, &str) -> Vec<u8> + Send + 'static,
+    ) -> (Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/theme.css", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let log = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0];
+                    if stream.read(&mut byte).await.unwrap() == 0 {
+                        break;
+                    }
+                    head.push(byte[0]);
+                }
 
-        _ => {
-            bail!("unsupported archive extension: {ext:?}");
+                let head = String::from_utf8(head).unwrap().to_ascii_lowercase();
+                let index = {
+                    let mut log = log.lock().unwrap();
+                    log.push(head.clone());
+                    log.len() - 1
+                };
+
+                stream.write_all(&respond(index, &head)).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    async fn run(url: &Url, part_path: &Path) -> Vec<u8> {
+        let mut sink = Sink::open(part_path, None, None).await.unwrap();
+        let mut progress = Progress::start(url, true).unwrap();
+
+        download(url, &HeaderMap::new(), &mut sink, &mut progress)
+            .await
+            .unwrap();
+
+        let (digests, _, _) = sink.finish().await.unwrap();
+        let data = std::fs::read(part_path).unwrap();
+        assert_eq!(digests.sha256_hex(), integrity::sha256_hex(&data));
+
+        data
+    }
+
+    /// Responds with the rest of the body if the request resumes at `from` without a validator or
+    /// with the current one, or with the whole body otherwise.
+    fn resume(head: &str, from: usize) -> Vec<u8> {
+        let body = body();
+        let etag = format!("etag: {ETAG}");
+
+        if head.contains(&format!("range: bytes={from}-"))
+            && (!head.contains("if-range:") || head.contains(&format!("if-range: {ETAG}")))
+        {
+            let range = format!(
+                "content-range: bytes {from}-{}/{}",
+                body.len() - 1,
+                body.len()
+            );
+            response(
+                "206 Partial Content",
+                &[etag, range],
+                &body[from..],
+                body.len() - from,
+            )
+        } else {
+            response("200 OK", &[etag], &body, body.len())
         }
     }
 
-    strip_root(temp_extract_path).await?;
-    super::copy_dir_all(temp_extract_path, target).await?;
+    #[tokio::test]
+    async fn retries_with_retry_after() {
+        let (url, requests) = serve(|index, _| {
+            if index == 0 {
+                response(
+                    "503 Service Unavailable",
+                    &["retry-after: 0".to_owned()],
+                    &[],
+                    0,
+                )
+            } else {
+                response("200 OK", &[], &body(), body().len())
+            }
+        })
+        .await;
+
+        let temp = TempDir::new().unwrap();
+        assert_eq!(run(&url, &temp.path().join("a.part")).await, body());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reports_exhausted_rate_limit() {
+        let (url, requests) = serve(|_, _| {
+            response(
+                "403 Forbidden",
+                &["x-ratelimit-remaining: 0".to_owned()],
+                &[],
+                0,
+            )
+        })
+        .await;
 
-    fs::remove_dir_all(temp_extract_path).await?;
+        let err = client()
+            .get(url.clone())
+            .send_with_retry()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("rate limit"), "{err}");
+        assert!(err.to_string().contains("`tokens`"), "{err}");
+        assert_eq!(requests.lock().unwrap().len(), 1);
 
-    Ok(())
+        // a token doesn't help if the request already used one
+        let err = client()
+            .get(url)
+            .header(header::AUTHORIZATION, "Bearer t")
+            .send_with_retry()
+            .await
+            .unwrap_err();
+        assert!(!err.to_string().contains("`tokens`"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn resumes_dropped_connection() {
+        let half = body().len() / 2;
+
+        let (url, requests) = serve(move |index, head| {
+            if index == 0 {
+                let body = body();
+                response(
+                    "200 OK",
+                    &[format!("etag: {ETAG}")],
+                    &body[..half],
+                    body.len(),
+                )
+            } else {
+                resume(head, half)
+            }
+        })
+        .await;
+
+        let temp = TempDir::new().unwrap();
+        assert_eq!(run(&url, &temp.path().join("a.part")).await, body());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains(&format!("range: bytes={half}-")));
+    }
+
+    #[tokio::test]
+    async fn resumes_across_runs() {
+        let half = body().len() / 2;
+        let (url, requests) = serve(move |_, head| resume(head, half)).await;
+
+        let temp = TempDir::new().unwrap();
+        let part_path = temp.path().join("a.part");
+        std::fs::write(&part_path, &body()[..half]).unwrap();
+        std::fs::write(cache::validator_path(&part_path), ETAG).unwrap();
+
+        assert_eq!(run(&url, &part_path).await, body());
+        assert!(requests.lock().unwrap()[0].contains("if-range"));
+    }
+
+    #[tokio::test]
+    async fn discards_changed_partial_file() {
+        let half = body().len() / 2;
+        let (url, requests) = serve(move |_, head| resume(head, half)).await;
+
+        let temp = TempDir::new().unwrap();
+
+        // the remote file changed since the partial file was written
+        let stale = temp.path().join("stale.part");
+        std::fs::write(&stale, vec![0; half]).unwrap();
+        std::fs::write(cache::validator_path(&stale), "\"v0\"").unwrap();
+        assert_eq!(run(&url, &stale).await, body());
+
+        // the partial file has no validator
+        let unvalidated = temp.path().join("unvalidated.part");
+        std::fs::write(&unvalidated, vec![0; half]).unwrap();
+        assert_eq!(run(&url, &unvalidated).await, body());
+        assert!(!requests.lock().unwrap()[1].contains("range"));
+    }
 }
@@ -0,0 +1,208 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{path::Path, process::Stdio};
+use tokio::{fs, process::Command};
+
+use anstream::eprintln;
+use eyre::{Result, bail, eyre};
+use owo_colors::OwoColorize as _;
+use temp_dir::TempDir;
+use url::Url;
+
+use super::{
+    archive::{self, Format},
+    cache,
+};
+use crate::source::{GitRef, RetrieveOptions};
+
+async fn git(dir: Option<&Path>, args: &[&str]) -> Result<Vec<u8>> {
+    let mut cmd = Command::new("git");
+    cmd.args(args).env("GIT_TERMINAL_PROMPT", "0");
+
+    if let Some(dir) = dir {
+        cmd.current_dir(dir);
+    }
+
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let output = cmd
+        .output()
+        .await
+        .map_err(|e| eyre!("failed to run git: {e}"))?;
+
+    if !output.status.success() {
+        bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+fn refspec(r#ref: Option<&GitRef>) -> String {
+    match r#ref {
+        None => "HEAD".to_owned(),
+        Some(GitRef::Auto(name) | GitRef::Commit(name)) => name.to_owned(),
+        Some(GitRef::Branch(name)) => format!("refs/heads/{name}"),
+        Some(GitRef::Tag(name)) => format!("refs/tags/{name}"),
+    }
+}
+
+/// Resolves a ref in a remote repository to a commit SHA without fetching it.
+///
+/// Returns `None` if the ref cannot be found among the remote's refs (e.g. an abbreviated commit SHA).
+pub async fn resolve(url: &Url, r#ref: Option<&GitRef>) -> Result<Option<String>> {
+    if let Some(GitRef::Commit(sha)) = r#ref {
+        return Ok((sha.len() == 40).then(|| sha.clone()));
+    }
+
+    let refspec = refspec(r#ref);
+    let output = String::from_utf8(
+        git(
+            None,
+            &["ls-remote", "--end-of-options", url.as_str(), &refspec],
+        )
+        .await?,
+    )?;
+
+    let refs = output
+        .lines()
+        .filter_map(|l| l.split_once('\t'))
+        .collect::<Vec<_>>();
+
+    let candidates = match r#ref {
+        None => vec!["HEAD".to_owned()],
+        Some(GitRef::Auto(name)) => vec![
+            format!("refs/heads/{name}"),
+            format!("refs/tags/{name}^{{}}"),
+            format!("refs/tags/{name}"),
+        ],
+        Some(GitRef::Branch(name)) => vec![format!("refs/heads/{name}")],
+        Some(GitRef::Tag(name)) => vec![
+            format!("refs/tags/{name}^{{}}"),
+            format!("refs/tags/{name}"),
+        ],
+        Some(GitRef::Commit(_)) => unreachable!(),
+    };
+
+    Ok(candidates.iter().find_map(|c| {
+        refs.iter()
+            .find(|(_, name)| name == c)
+            .map(|(sha, _)| (*sha).to_owned())
+    }))
+}
+
+/// Checks out a ref of a remote repository into `target` without Git metadata.
+///
+/// Fetched objects are kept in a bare repository in the cache, which is used exclusively in offline mode.
+pub async fn checkout(
+    url: &Url,
+    r#ref: Option<&GitRef>,
+    target: &Path,
+    options: &RetrieveOptions,
+) -> Result<()> {
+    let repo = cache::git_repo(url)?;
+    let _guard = cache::lock(&repo).await;
+
+    if !repo.exists() {
+        if options.offline {
+            bail!("{url} is not available in the cache");
+        }
+
+        fs::create_dir_all(&repo).await?;
+        git(Some(&repo), &["init", "--quiet", "--bare"]).await?;
+    }
+
+    let rev = if options.offline {
+        let Some(GitRef::Commit(sha)) = r#ref else {
+            bail!("{url} must be pinned to a commit to be used in offline mode");
+        };
+
+        if !options.quiet {
+            eprintln!(
+                "{} {} {}",
+                "╰".cyan().dimmed(),
+                url.dimmed(),
+                "(cached)".green().dimmed()
+            );
+        }
+
+        sha.to_owned()
+    } else {
+        if !options.quiet {
+            eprintln!("{} {}", "╰".cyan().dimmed(), url.dimmed());
+        }
+
+        let refspec = refspec(r#ref);
+
+        let shallow = git(
+            Some(&repo),
+            &[
+                "fetch",
+                "--quiet",
+                "--depth",
+                "1",
+                "--end-of-options",
+                url.as_str(),
+                &refspec,
+            ],
+        )
+        .await;
+
+        match (shallow, r#ref) {
+            (Ok(_), _) => "FETCH_HEAD".to_owned(),
+
+            // servers may not allow fetching commits by (abbreviated) SHA directly,
+            // so fall back to fetching everything and letting git resolve the commit locally
+            (Err(_), Some(GitRef::Commit(sha))) => {
+                git(
+                    Some(&repo),
+                    &[
+                        "fetch",
+                        "--quiet",
+                        "--end-of-options",
+                        url.as_str(),
+                        "+refs/heads/*:refs/heads/*",
+                        "+refs/tags/*:refs/tags/*",
+                    ],
+                )
+                .await?;
+
+                sha.to_owned()
+            }
+
+            (Err(err), _) => return Err(err),
+        }
+    };
+
+    let commit = format!("{rev}^{{commit}}");
+
+    git(Some(&repo), &["rev-parse", "--quiet", "--verify", &commit])
+        .await
+        .map_err(|_| eyre!("{rev} of {url} is not available in the cache"))?;
+
+    let temp_dir = TempDir::new()?;
+    let tarball = temp_dir.child("source.tar");
+
+    git(
+        Some(&repo),
+        &[
+            "archive",
+            "--format=tar",
+            "--output",
+            &tarball.to_string_lossy(),
+            &commit,
+        ],
+    )
+    .await?;
+
+    archive::extract_file(&tarball, Format::Tar, target, false, options.limits).await?;
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fmt::{self, Write as _},
+    path::Path,
+    str::FromStr,
+};
+use tokio::{fs::File, io::AsyncReadExt as _};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use eyre::{Result, bail};
+use sha2::{Digest as _, Sha256, Sha384, Sha512};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Algorithm {
+    fn digest_len(self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha384 => 48,
+            Self::Sha512 => 64,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+        })
+    }
+}
+
+/// An expected hash of downloaded data, parsed from a hex-encoded SHA-256 digest
+/// or a [Subresource Integrity](https://www.w3.org/TR/SRI/) string like `sha256-<base64>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Integrity {
+    algorithm: Algorithm,
+    digest: Vec<u8>,
+}
+
+impl Integrity {
+    pub fn sha256_hex(hex: &str) -> Result<Self> {
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("invalid SHA-256 digest: {hex:?}");
+        }
+
+        let digest = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            algorithm: Algorithm::Sha256,
+            digest,
+        })
+    }
+
+    pub fn verify(&self, hashes: &Hashes) -> Result<()> {
+        let actual = Self {
+            algorithm: self.algorithm,
+            digest: match self.algorithm {
+                Algorithm::Sha256 => hashes.sha256.clone(),
+                Algorithm::Sha384 => hashes.sha384.clone(),
+                Algorithm::Sha512 => hashes.sha512.clone(),
+            },
+        };
+
+        if &actual != self {
+            bail!("integrity mismatch\n  expected: {self}\n       got: {actual}");
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Integrity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.algorithm, BASE64.encode(&self.digest))
+    }
+}
+
+impl FromStr for Integrity {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((algorithm, digest)) = s.split_once('-') else {
+            return Self::sha256_hex(s);
+        };
+
+        let algorithm = match algorithm {
+            "sha256" => Algorithm::Sha256,
+            "sha384" => Algorithm::Sha384,
+            "sha512" => Algorithm::Sha512,
+            _ => bail!("unsupported integrity algorithm: {algorithm:?}"),
+        };
+
+        let digest = BASE64.decode(digest)?;
+        if digest.len() != algorithm.digest_len() {
+            bail!("invalid {algorithm} digest length in {s:?}");
+        }
+
+        Ok(Self { algorithm, digest })
+    }
+}
+
+/// Incrementally computes the digests of data for every supported algorithm.
+#[derive(Clone, Debug, Default)]
+pub struct Hasher {
+    sha256: Sha256,
+    sha384: Sha384,
+    sha512: Sha512,
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        self.sha384.update(data);
+        self.sha512.update(data);
+    }
+
+    pub fn finalize(self) -> Hashes {
+        Hashes {
+            sha256: self.sha256.finalize().to_vec(),
+            sha384: self.sha384.finalize().to_vec(),
+            sha512: self.sha512.finalize().to_vec(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Hashes {
+    sha256: Vec<u8>,
+    sha384: Vec<u8>,
+    sha512: Vec<u8>,
+}
+
+impl Hashes {
+    pub fn sha256_hex(&self) -> String {
+        self.sha256.iter().fold(String::new(), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        })
+    }
+}
+
+pub async fn hash_file(path: &Path) -> Result<Hashes> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Hasher::default();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+pub fn verify_all(integrity: &[Integrity], hashes: &Hashes) -> Result<()> {
+    for i in integrity {
+        i.verify(hashes)?;
+    }
+
+    Ok(())
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
@@ -10,8 +10,34 @@ use sysinfo::{ProcessRefreshKind, RefreshKind, System};
 use async_recursion::async_recursion;
 use eyre::{Result, bail};
 
+pub mod archive;
+pub mod cache;
 pub mod download;
+pub mod git;
+pub mod integrity;
+pub mod netrc;
+pub mod transaction;
 
+#[cfg(unix)]
+async fn copy_symlink(src: &Path, dst: &Path) -> Result<()> {
+    fs::symlink(fs::read_link(src).await?, dst).await?;
+    Ok(())
+}
+
+#[cfg(windows)]
+async fn copy_symlink(src: &Path, dst: &Path) -> Result<()> {
+    let target = fs::read_link(src).await?;
+
+    if fs::metadata(src).await.is_ok_and(|m| m.is_dir()) {
+        fs::symlink_dir(target, dst).await?;
+    } else {
+        fs::symlink_file(target, dst).await?;
+    }
+
+    Ok(())
+}
+
+/// Recursively copies a directory, recreating symlinks instead of following them.
 #[async_recursion]
 pub async fn copy_dir_all(src: &Path, dst: &Path) -> Result<()> {
     fs::create_dir_all(dst).await?;
@@ -19,7 +45,9 @@ pub async fn copy_dir_all(src: &Path, dst: &Path) -> Result<()> {
     let mut dir_entries = fs::read_dir(src).await?;
     while let Ok(Some(entry)) = dir_entries.next_entry().await {
         let ty = entry.file_type().await?;
-        if ty.is_dir() {
+        if ty.is_symlink() {
+            copy_symlink(&entry.path(), &dst.join(entry.file_name())).await?;
+        } else if ty.is_dir() {
             copy_dir_all(&entry.path(), &dst.join(entry.file_name())).await?;
         } else {
             fs::copy(entry.path(), dst.join(entry.file_name())).await?;
@@ -0,0 +1,121 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{io, path::PathBuf};
+use tokio::fs;
+
+use eyre::Result;
+
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    pub login: String,
+    pub password: String,
+}
+
+/// Returns the path of the user's netrc file, which can be overridden with the `NETRC` environment variable.
+fn path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("NETRC") {
+        return Some(path.into());
+    }
+
+    let name = if cfg!(windows) { "_netrc" } else { ".netrc" };
+    etcetera::home_dir().ok().map(|home| home.join(name))
+}
+
+/// Splits a netrc file into tokens, skipping comments and macro definitions.
+fn tokenize(contents: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut in_macdef = false;
+
+    for line in contents.lines() {
+        if in_macdef {
+            // macro definitions end at the first empty line
+            in_macdef = !line.trim().is_empty();
+            continue;
+        }
+
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        for token in line.split_whitespace() {
+            if token == "macdef" {
+                in_macdef = true;
+                break;
+            }
+
+            tokens.push(token);
+        }
+    }
+
+    tokens
+}
+
+/// Finds the credentials for `host` in the contents of a netrc file, falling back to the `default` entry.
+fn parse(contents: &str, host: &str) -> Option<Credentials> {
+    enum Entry {
+        Host,
+        Default,
+        Other,
+    }
+
+    let mut tokens = tokenize(contents).into_iter();
+
+    let mut entry = Entry::Other;
+    let mut found: Option<Credentials> = None;
+    let mut fallback: Option<Credentials> = None;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                if found.is_some() {
+                    break;
+                }
+
+                entry = if token == "default" {
+                    fallback = Some(Credentials::default());
+                    Entry::Default
+                } else if tokens.next() == Some(host) {
+                    found = Some(Credentials::default());
+                    Entry::Host
+                } else {
+                    Entry::Other
+                };
+            }
+            "login" | "password" | "account" => {
+                let value = tokens.next().unwrap_or_default();
+
+                let credentials = match entry {
+                    Entry::Host => found.as_mut(),
+                    Entry::Default => fallback.as_mut(),
+                    Entry::Other => None,
+                };
+
+                if let Some(credentials) = credentials {
+                    match token {
+                        "login" => value.clone_into(&mut credentials.login),
+                        "password" => value.clone_into(&mut credentials.password),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    found.or(fallback)
+}
+
+/// Looks up the credentials for `host` in the user's netrc file, if there is one.
+pub async fn lookup(host: &str) -> Result<Option<Credentials>> {
+    let Some(path) = path() else {
+        return Ok(None);
+    };
+
+    match fs::read_to_string(&path).await {
+        Ok(contents) => Ok(parse(&contents, host)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,146 @@
+// SPDX-FileCopyrightText: 2026 Ryan Cao <hello@ryanccn.dev>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+use eyre::{Result, eyre};
+
+#[derive(Debug)]
+enum Step {
+    /// A file or directory that did not exist before
+    Created(PathBuf),
+    /// A file or directory that was renamed
+    Renamed { from: PathBuf, to: PathBuf },
+    /// A file or directory that was moved aside, to be restored on rollback
+    Saved { path: PathBuf, saved: PathBuf },
+}
+
+/// A set of changes to the filesystem that can be rolled back as a whole.
+///
+/// Changes have to be recorded before they are made, so that a failure halfway through
+/// a change is rolled back as well.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    steps: Vec<Step>,
+}
+
+/// Removes a file or directory, if it exists.
+async fn remove_path(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path).await?,
+        Ok(_) => fs::remove_file(path).await?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    Ok(())
+}
+
+fn saved_path(path: &Path) -> Result<PathBuf> {
+    let mut name = OsString::from(
+        path.file_name()
+            .ok_or_else(|| eyre!("could not obtain file name of {}", path.display()))?,
+    );
+    name.push(".nyoom-rollback");
+
+    Ok(path.with_file_name(name))
+}
+
+impl Transaction {
+    fn is_tracked(&self, path: &Path) -> bool {
+        self.steps.iter().any(|step| match step {
+            Step::Created(p) | Step::Saved { path: p, .. } => p == path,
+            Step::Renamed { to, .. } => to == path,
+        })
+    }
+
+    /// Records that `path` is about to be created.
+    pub fn create(&mut self, path: &Path) {
+        self.steps.push(Step::Created(path.to_owned()));
+    }
+
+    /// Saves a copy of a file that is about to be modified, or records that it is about to be
+    /// created if it doesn't exist yet.
+    pub async fn modify(&mut self, path: &Path) -> Result<()> {
+        if self.is_tracked(path) {
+            return Ok(());
+        }
+
+        if !path.exists() {
+            self.create(path);
+            return Ok(());
+        }
+
+        let saved = saved_path(path)?;
+        remove_path(&saved).await?;
+        fs::copy(path, &saved).await?;
+
+        self.steps.push(Step::Saved {
+            path: path.to_owned(),
+            saved,
+        });
+
+        Ok(())
+    }
+
+    /// Moves a file or directory out of the way, deleting it when the transaction is committed.
+    pub async fn remove(&mut self, path: &Path) -> Result<()> {
+        let saved = saved_path(path)?;
+        remove_path(&saved).await?;
+
+        self.steps.push(Step::Saved {
+            path: path.to_owned(),
+            saved: saved.clone(),
+        });
+
+        fs::rename(path, &saved).await?;
+
+        Ok(())
+    }
+
+    /// Renames a file or directory.
+    pub async fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to).await?;
+
+        self.steps.push(Step::Renamed {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    /// Keeps all changes, deleting the copies saved for rolling back.
+    pub async fn commit(self) -> Result<()> {
+        for step in self.steps {
+            if let Step::Saved { saved, .. } = step {
+                remove_path(&saved).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reverts all changes in reverse order.
+    pub async fn rollback(self) -> Result<()> {
+        for step in self.steps.into_iter().rev() {
+            match step {
+                Step::Created(path) => remove_path(&path).await?,
+                Step::Renamed { from, to } => fs::rename(to, from).await?,
+                Step::Saved { path, saved } => {
+                    if saved.exists() {
+                        remove_path(&path).await?;
+                        fs::rename(saved, path).await?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}